@@ -1,19 +1,29 @@
 use colored::*;
-use hf2::utils::{elf_to_bin, flash_bin, vendor_map};
+use hf2::cli::{ArchArgs, DeviceArgs};
+use hf2::utils::{check_arch, elf_to_bin_and_arch, flash_bin, vendor_map, UtilError};
 use hidapi::{HidApi, HidDevice};
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
 use std::time::Instant;
+use structopt::clap::Shell;
 use structopt::StructOpt;
 
 fn main() {
     // Initialize the logging backend.
-    pretty_env_logger::init();
+    // HF2_LOG logs just hf2, otherwise RUST_LOG as usual
+    hf2::init_logging();
+    let _ = pretty_env_logger::try_init();
 
     // Get commandline options.
     // Skip the first arg which is the calling application name.
     let opt = Opt::from_iter(std::env::args().skip(1));
 
+    // completions dont need a project or a device
+    if let Some(Cmd::completions { shell }) = opt.cmd {
+        Opt::clap().gen_completions_to("cargo-hf2", shell, &mut std::io::stdout());
+        return;
+    }
+
     // Try and get the cargo project information.
     let project = cargo_project::Project::query(".").expect("Couldn't parse the Cargo.toml");
 
@@ -46,15 +56,9 @@ fn main() {
     // Remove first two args which is the calling application name and the `hf2` command from cargo.
     let mut args: Vec<_> = std::env::args().skip(2).collect();
 
-    // todo, keep as iter. difficult because we want to filter map remove two items at once.
     // Remove our args as cargo build does not understand them.
-    let flags = ["--pid", "--vid"].iter();
-    for flag in flags {
-        if let Some(index) = args.iter().position(|x| x == flag) {
-            args.remove(index);
-            args.remove(index);
-        }
-    }
+    DeviceArgs::remove_from(&mut args);
+    ArchArgs::remove_from(&mut args);
 
     let status = Command::new("cargo")
         .arg("build")
@@ -72,7 +76,19 @@ fn main() {
 
     let api = HidApi::new().expect("Couldn't find system usb");
 
-    let d = if let (Some(v), Some(p)) = (opt.vid, opt.pid) {
+    let d = if let (Some(v), Some(p), Some(i)) =
+        (opt.device.vid, opt.device.pid, opt.device.interface)
+    {
+        api.device_list()
+            .find(|device_info| {
+                device_info.vendor_id() == v
+                    && device_info.product_id() == p
+                    && device_info.interface_number() == i
+            })
+            .expect("Are you sure device is plugged in and in bootloader mode?")
+            .open_device(&api)
+            .expect("Are you sure device is plugged in and in bootloader mode?")
+    } else if let (Some(v), Some(p)) = (opt.device.vid, opt.device.pid) {
         api.open(v, p)
             .expect("Are you sure device is plugged in and in bootloader mode?")
     } else {
@@ -87,7 +103,11 @@ fn main() {
 
         for device_info in api.device_list() {
             if let Some(products) = vendor.get(&device_info.vendor_id()) {
-                if products.contains(&device_info.product_id()) {
+                if products.contains(&device_info.product_id())
+                    && hf2::is_hf2_usage(device_info.usage_page(), device_info.usage())
+                    && (opt.device.interface.is_none()
+                        || opt.device.interface == Some(device_info.interface_number()))
+                {
                     if let Ok(d) = device_info.open_device(&api) {
                         device = Some(d);
                         break;
@@ -107,7 +127,7 @@ fn main() {
 
     println!("    {} {:?}", "Flashing".green().bold(), path);
 
-    let (binary, address) = elf_to_bin(path).unwrap();
+    let (binary, address, arch) = elf_to_bin_and_arch(path).unwrap();
 
     // Start timer.
     let instant = Instant::now();
@@ -115,6 +135,20 @@ fn main() {
     let bininfo = hf2::bin_info(&d).expect("bin_info failed");
     log::debug!("{:?}", bininfo);
 
+    if let Err(UtilError::ArchMismatch { image, device }) = check_arch(arch, bininfo.family_id) {
+        if opt.arch.force_arch {
+            log::warn!("image targets {}, device is {}", image, device);
+        } else {
+            eprintln!(
+                "image targets {}, device {:?} is {}, --force-arch to flash it anyway",
+                image,
+                bininfo.family_id.unwrap(),
+                device
+            );
+            std::process::exit(1);
+        }
+    }
+
     flash_bin(&binary, address, &bininfo, &d).unwrap();
 
     // Stop timer.
@@ -139,16 +173,21 @@ fn exit_with_process_status(status: std::process::ExitStatus) -> ! {
     std::process::exit(status)
 }
 
-fn parse_hex_16(input: &str) -> Result<u16, std::num::ParseIntError> {
-    if input.starts_with("0x") {
-        u16::from_str_radix(&input[2..], 16)
-    } else {
-        input.parse::<u16>()
-    }
+#[allow(non_camel_case_types)]
+#[derive(Debug, StructOpt)]
+enum Cmd {
+    /// print a shell completion script to stdout, ex `cargo hf2 completions bash > /etc/bash_completion.d/cargo-hf2`
+    completions {
+        #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+        shell: Shell,
+    },
 }
 
 #[derive(Debug, StructOpt)]
 struct Opt {
+    #[structopt(subcommand)]
+    cmd: Option<Cmd>,
+
     // `cargo build` arguments
     #[structopt(name = "binary", long = "bin")]
     bin: Option<String>,
@@ -169,8 +208,31 @@ struct Opt {
     #[structopt(long)]
     features: Vec<String>,
 
-    #[structopt(name = "pid", long = "pid", parse(try_from_str = parse_hex_16))]
-    pid: Option<u16>,
-    #[structopt(name = "vid", long = "vid",  parse(try_from_str = parse_hex_16))]
-    vid: Option<u16>,
+    #[structopt(flatten)]
+    device: DeviceArgs,
+    #[structopt(flatten)]
+    arch: ArchArgs,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completions_include_forwarded_and_device_flags() {
+        let mut script = vec![];
+        Opt::clap().gen_completions_to("cargo-hf2", Shell::Bash, &mut script);
+        let script = String::from_utf8(script).unwrap();
+
+        for expected in &[
+            "--release",
+            "--example",
+            "--vid",
+            "--pid",
+            "--force-arch",
+            "completions",
+        ] {
+            assert!(script.contains(expected), "missing {}", expected);
+        }
+    }
 }
@@ -0,0 +1,36 @@
+use hf2::utils::{read_image, FirmwareProvider, Image, ImageSpec, UtilError};
+
+/// Images over http(s), location is the URL.
+pub struct HttpProvider;
+
+impl FirmwareProvider for HttpProvider {
+    fn open(&self, spec: &ImageSpec) -> Result<Image, String> {
+        let response = ureq::get(&spec.location).call().map_err(|e| match e {
+            ureq::Error::Status(status, _) => format!("{} answered {}", spec.location, status),
+            e => format!("couldnt fetch {}: {}", spec.location, e),
+        })?;
+        let length = response
+            .header("Content-Length")
+            .and_then(|length| length.parse().ok());
+
+        Ok(Image {
+            reader: Box::new(response.into_reader()),
+            length,
+            sha256: None,
+        })
+    }
+}
+
+/// The image at url, read into memory. Exits with 3 if it cant be fetched, so scripts can tell a
+/// bad url from a failed flash or integrity check, and 2 like any integrity check if it isnt what
+/// it declared.
+pub fn fetch(url: &str) -> Vec<u8> {
+    read_image(&HttpProvider, &ImageSpec::new(url)).unwrap_or_else(|e| {
+        match e {
+            UtilError::Integrity(e) => crate::integrity_failed(url, e),
+            UtilError::Provider(message) => eprintln!("{}", message),
+            e => eprintln!("{:?}", e),
+        }
+        std::process::exit(3);
+    })
+}
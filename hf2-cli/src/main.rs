@@ -1,20 +1,380 @@
-use hf2::utils::{elf_to_bin, flash_bin, vendor_map, verify_bin};
-use hidapi::{HidApi, HidDevice};
+use hf2::cli::{ArchArgs, DeviceArgs};
+use hf2::utils::{
+    check_arch, elf_to_bin_and_arch, flash_bin, flash_bin_with_options, flash_fingerprint,
+    read_checked, slice_image, vendor_map, verify_bin, FlashOptions, IntegrityCheck,
+    IntegrityError, UtilError,
+};
+use hf2::DeviceFilter;
+use hidapi::{DeviceInfo, HidApi, HidDevice};
+use render::{key_values, ColorChoice, Style, Table};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use structopt::clap::Shell;
 use structopt::StructOpt;
 
+#[cfg(feature = "http")]
+mod http;
+mod render;
+mod udev;
+#[cfg(feature = "watch")]
+mod watch;
+
 fn main() {
-    pretty_env_logger::init();
+    // HF2_LOG logs just hf2, otherwise RUST_LOG as usual
+    hf2::init_logging();
+    let _ = pretty_env_logger::try_init();
 
     let args = Opt::from_args();
+    let style = Style::new(args.color, args.plain);
+
+    // completions dont need a device
+    if let Cmd::completions { shell } = args.cmd {
+        Opt::clap().gen_completions_to("hf2", shell, &mut std::io::stdout());
+        return;
+    }
+
+    // nor do udev rules
+    if let Cmd::udev_rules { install } = args.cmd {
+        let rules = udev::rules(&vendor_map());
+        if !install {
+            print!("{}", rules);
+            return;
+        }
+        match udev::install(&rules, std::path::Path::new(udev::RULES_DIR)) {
+            Ok(path) => println!("wrote {}", path.display()),
+            Err(message) => {
+                eprintln!("{}", message);
+                std::process::exit(1);
+            }
+        }
+        udev::reload();
+        return;
+    }
 
     let api = HidApi::new().expect("Couldn't find system usb");
 
-    let d = if let (Some(v), Some(p)) = (args.vid, args.pid) {
-        api.open(v, p)
-            .expect("Are you sure device is plugged in and in bootloader mode?")
+    // listing only reads enumeration metadata, nothing gets opened
+    if let Cmd::list = args.cmd {
+        if let Err(message) = args.select.as_ref().map_or(Ok(()), list_filter) {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+        let vendor = vendor_map();
+        let listed: Vec<DeviceSummary> = summaries(&api)
+            .into_iter()
+            .filter(|summary| {
+                summary.matches(
+                    args.device.vid,
+                    args.device.pid,
+                    args.device.interface,
+                    &vendor,
+                )
+            })
+            .filter(|summary| match &args.select {
+                Some(filter) => filter.matches_ids(summary.vid, summary.pid, summary.serial()),
+                None => true,
+            })
+            .collect();
+        print!("{}", device_table(&listed).render(&style));
+        return;
+    }
+
+    #[cfg(feature = "watch")]
+    if let Cmd::watch {
+        file,
+        address,
+        skip,
+    } = &args.cmd
+    {
+        let mut api = api;
+        watch::watch(&mut api, &args, file, *address, *skip);
+    }
+
+    let d = open(&api, &args);
+
+    match args.cmd {
+        Cmd::resetIntoApp => hf2::reset_into_app(&d).unwrap(),
+        Cmd::resetIntoBootloader => hf2::reset_into_bootloader(&d).unwrap(),
+        Cmd::info => info(&d, &style),
+        Cmd::bininfo => bininfo(&d),
+        Cmd::dmesg => dmesg(&d),
+        Cmd::flash {
+            file,
+            #[cfg(feature = "http")]
+            url,
+            address,
+            skip,
+            dry_run,
+            fill_to_end,
+            sha256,
+            #[cfg(feature = "minisign")]
+            verify_sig,
+        } => {
+            #[cfg(feature = "http")]
+            let (source, url) = match url {
+                Some(url) => (PathBuf::from(&url), Some(url)),
+                None => (file.expect("--file or --url is required"), None),
+            };
+            #[cfg(not(feature = "http"))]
+            let source = file;
+
+            #[cfg(feature = "minisign")]
+            let check = integrity_check(&source, sha256, verify_sig);
+            #[cfg(not(feature = "minisign"))]
+            let check = integrity_check(&source, sha256);
+
+            // a download is checked once it is in memory
+            #[cfg(feature = "http")]
+            let fetched = url.map(|url| {
+                let binary = http::fetch(&url);
+                if let Some(check) = &check {
+                    check
+                        .verify(&binary)
+                        .unwrap_or_else(|e| integrity_failed(source.display(), e));
+                }
+                binary
+            });
+            #[cfg(not(feature = "http"))]
+            let fetched: Option<Vec<u8>> = None;
+
+            let binary = match (fetched, check) {
+                (Some(binary), _) => binary,
+                (None, Some(check)) => get_checked_binary(&source, &check),
+                (None, None) => get_binary(source),
+            };
+            let bininfo = hf2::bin_info(&d).expect("bin_info failed");
+            log::debug!("{:?}", bininfo);
+
+            let options = FlashOptions {
+                dry_run,
+                fill_to_end,
+                skip: skip as usize,
+                ..Default::default()
+            };
+            let result = flash_bin_with_options(&binary, address, &bininfo, &options, &d).unwrap();
+            if dry_run {
+                let (offset, _) = slice_image(&binary, skip as usize);
+                let address = address
+                    .checked_add(offset)
+                    .expect("--skip moves the address past 32 bits");
+                println!(
+                    "Dry run ok, would write {} pages at {:#010X}",
+                    result.pages_planned, address
+                );
+            } else {
+                println!("Success")
+            }
+        }
+        Cmd::verify {
+            file,
+            address,
+            skip,
+        } => {
+            let binary = get_binary(file);
+            let (offset, binary) = slice_image(&binary, skip as usize);
+            let address = address
+                .checked_add(offset)
+                .expect("--skip moves the address past 32 bits");
+            let bininfo = hf2::bin_info(&d).expect("bin_info failed");
+            log::debug!("{:?}", bininfo);
+
+            verify_bin(binary, address, &bininfo, &d).unwrap();
+            println!("Success")
+        }
+        Cmd::elf {
+            path,
+            arch: ArchArgs { force_arch },
+        } => {
+            let (binary, address, arch) = elf_to_bin_and_arch(path).unwrap();
+
+            let bininfo = hf2::bin_info(&d).expect("bin_info failed");
+            log::debug!("{:?}", bininfo);
+
+            if let Err(UtilError::ArchMismatch { image, device }) =
+                check_arch(arch, bininfo.family_id)
+            {
+                if force_arch {
+                    log::warn!("image targets {}, device is {}", image, device);
+                } else {
+                    eprintln!(
+                        "image targets {}, device {:?} is {}, --force-arch to flash it anyway",
+                        image,
+                        bininfo.family_id.unwrap(),
+                        device
+                    );
+                    std::process::exit(1);
+                }
+            }
+
+            flash_bin(&binary, address, &bininfo, &d).unwrap();
+        }
+        Cmd::fingerprint {
+            address,
+            pages,
+            json,
+            expect,
+        } => fingerprint(&d, address, pages, json, expect, &style),
+        Cmd::completions { .. } | Cmd::list | Cmd::udev_rules { .. } => unreachable!(),
+        #[cfg(feature = "watch")]
+        Cmd::watch { .. } => unreachable!(),
+    }
+}
+
+/// What enumeration tells us about a device without opening it.
+#[derive(Debug, Clone, PartialEq)]
+struct DeviceSummary {
+    vid: u16,
+    pid: u16,
+    /// As the device reports it, blank on some clone boards.
+    serial: Option<String>,
+    /// vid:pid:serial, or vid:pid@path when the serial is blank or shared with another device.
+    identity: String,
+    path: String,
+    usage_page: u16,
+    usage: u16,
+    interface: i32,
+}
+
+impl From<&DeviceInfo> for DeviceSummary {
+    fn from(device_info: &DeviceInfo) -> Self {
+        let vid = device_info.vendor_id();
+        let pid = device_info.product_id();
+        let path = device_info.path().to_string_lossy().into_owned();
+        Self {
+            vid,
+            pid,
+            serial: device_info.serial_number().map(String::from),
+            identity: format!("{:04x}:{:04x}@{}", vid, pid, path),
+            path,
+            usage_page: device_info.usage_page(),
+            usage: device_info.usage(),
+            interface: device_info.interface_number(),
+        }
+    }
+}
+
+/// Every enumerated device with its identity.
+fn summaries(api: &HidApi) -> Vec<DeviceSummary> {
+    let mut summaries: Vec<DeviceSummary> = api.device_list().map(DeviceSummary::from).collect();
+    assign_identities(&mut summaries);
+    summaries
+}
+
+/// Identifies devices by serial where that tells them apart, the rest keep the path based identity.
+/// Interfaces of one composite device share a serial, so it is only a duplicate across vid/pids or
+/// paths of different devices with the same interface.
+fn assign_identities(summaries: &mut [DeviceSummary]) {
+    let mut seen: HashMap<(u16, u16, i32, String), usize> = HashMap::new();
+    for summary in summaries.iter() {
+        if let Some(serial) = summary.serial() {
+            *seen
+                .entry((summary.vid, summary.pid, summary.interface, serial.into()))
+                .or_default() += 1;
+        }
+    }
+
+    for summary in summaries.iter_mut() {
+        let unique = summary.serial().and_then(|serial| {
+            match seen.get(&(summary.vid, summary.pid, summary.interface, serial.into())) {
+                Some(1) => Some(serial.to_string()),
+                _ => None,
+            }
+        });
+        summary.identity = match unique {
+            Some(serial) => format!("{:04x}:{:04x}:{}", summary.vid, summary.pid, serial),
+            None => format!("{:04x}:{:04x}@{}", summary.vid, summary.pid, summary.path),
+        };
+    }
+}
+
+impl DeviceSummary {
+    /// The serial unless its blank.
+    fn serial(&self) -> Option<&str> {
+        self.serial
+            .as_deref()
+            .filter(|serial| !serial.trim().is_empty())
+    }
+
+    /// Same selection as opening a device, an explicit vid/pid or else any known bootloader.
+    fn matches(
+        &self,
+        vid: Option<u16>,
+        pid: Option<u16>,
+        interface: Option<i32>,
+        vendor: &HashMap<u16, Vec<u16>>,
+    ) -> bool {
+        let known = match (vid, pid) {
+            (Some(v), Some(p)) => self.vid == v && self.pid == p,
+            (Some(v), None) => self.vid == v,
+            (None, Some(p)) => self.pid == p,
+            (None, None) => {
+                vendor
+                    .get(&self.vid)
+                    .map(|products| products.contains(&self.pid))
+                    .unwrap_or(false)
+                    && hf2::is_hf2_usage(self.usage_page, self.usage)
+            }
+        };
+
+        known && (interface.is_none() || interface == Some(self.interface))
+    }
+}
+
+impl std::fmt::Display for DeviceSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} serial {} usage page 0x{:04x} interface {} at {}",
+            self.identity,
+            self.serial().unwrap_or("-"),
+            self.usage_page,
+            self.interface,
+            self.path
+        )
+    }
+}
+
+/// Opens the device the options pick, exiting if there isnt one.
+fn open(api: &HidApi, args: &Opt) -> HidDevice {
+    try_open(api, args).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    })
+}
+
+const NOT_FOUND: &str = "Are you sure device is plugged in and in bootloader mode?";
+
+/// The device the options pick, or why there isnt one.
+fn try_open(api: &HidApi, args: &Opt) -> Result<HidDevice, String> {
+    let d = if let Some(filter) = &args.select {
+        let style = Style::new(args.color, args.plain);
+        select(
+            api,
+            filter,
+            args.device.vid,
+            args.device.pid,
+            args.device.interface,
+            &style,
+        )?
+    } else if let (Some(v), Some(p), Some(i)) =
+        (args.device.vid, args.device.pid, args.device.interface)
+    {
+        api.device_list()
+            .find(|device_info| {
+                device_info.vendor_id() == v
+                    && device_info.product_id() == p
+                    && device_info.interface_number() == i
+            })
+            .ok_or_else(|| NOT_FOUND.to_string())
+            .and_then(|device_info| open_failed(device_info.open_device(api), device_info))?
+    } else if let (Some(v), Some(p)) = (args.device.vid, args.device.pid) {
+        // the device hid_open would pick, kept for the hint
+        api.device_list()
+            .find(|device_info| device_info.vendor_id() == v && device_info.product_id() == p)
+            .ok_or_else(|| NOT_FOUND.to_string())
+            .and_then(|device_info| open_failed(device_info.open_device(api), device_info))?
     } else {
         println!("no vid/pid provided..");
 
@@ -24,15 +384,22 @@ fn main() {
 
         for device_info in api.device_list() {
             if let Some(products) = vendor.get(&device_info.vendor_id()) {
-                if products.contains(&device_info.product_id()) {
-                    if let Ok(d) = device_info.open_device(&api) {
-                        device = Some(d);
-                        break;
+                if products.contains(&device_info.product_id())
+                    && hf2::is_hf2_usage(device_info.usage_page(), device_info.usage())
+                    && (args.device.interface.is_none()
+                        || args.device.interface == Some(device_info.interface_number()))
+                {
+                    match device_info.open_device(api) {
+                        Ok(d) => {
+                            device = Some(d);
+                            break;
+                        }
+                        Err(e) => log::warn!("couldnt open: {}{}", e, hint(device_info)),
                     }
                 }
             }
         }
-        device.expect("Are you sure device is plugged in and in bootloader mode?")
+        device.ok_or(NOT_FOUND)?
     };
 
     println!(
@@ -40,47 +407,124 @@ fn main() {
         d.get_manufacturer_string(),
         d.get_product_string()
     );
+    Ok(d)
+}
 
-    match args.cmd {
-        Cmd::resetIntoApp => hf2::reset_into_app(&d).unwrap(),
-        Cmd::resetIntoBootloader => hf2::reset_into_bootloader(&d).unwrap(),
-        Cmd::info => info(&d),
-        Cmd::bininfo => bininfo(&d),
-        Cmd::dmesg => dmesg(&d),
-        Cmd::flash { file, address } => {
-            let binary = get_binary(file);
-            let bininfo = hf2::bin_info(&d).expect("bin_info failed");
-            log::debug!("{:?}", bininfo);
+fn open_failed(
+    opened: Result<HidDevice, hidapi::HidError>,
+    device_info: &DeviceInfo,
+) -> Result<HidDevice, String> {
+    opened.map_err(|e| format!("couldnt open the device: {}{}", e, hint(device_info)))
+}
 
-            flash_bin(&binary, address, &bininfo, &d).unwrap();
-            println!("Success")
+/// udev::open_hint for the node of device_info.
+fn hint(device_info: &DeviceInfo) -> &'static str {
+    udev::open_hint(Path::new(&*device_info.path().to_string_lossy()))
+}
+
+/// list doesnt open devices, so a --select it can only check by opening them is an error naming
+/// those keys rather than a list of every device.
+fn list_filter(filter: &DeviceFilter) -> Result<(), String> {
+    if !filter.needs_probe() {
+        return Ok(());
+    }
+    Err(format!(
+        "list doesnt open devices so it cant select by {}, only by vid, pid and serial",
+        filter.probed_keys().join(", ")
+    ))
+}
+
+/// Opens the one device matching --select among the candidates --vid, --pid and --interface allow.
+/// Enumeration ids are checked first so only devices that could match get opened and probed.
+fn select(
+    api: &HidApi,
+    filter: &DeviceFilter,
+    vid: Option<u16>,
+    pid: Option<u16>,
+    interface: Option<i32>,
+    style: &Style,
+) -> Result<HidDevice, String> {
+    let vendor = vendor_map();
+    let mut matched = vec![];
+
+    for (device_info, summary) in api.device_list().zip(summaries(api)) {
+        if !summary.matches(vid, pid, interface, &vendor)
+            || !filter.matches_ids(summary.vid, summary.pid, summary.serial())
+        {
+            continue;
         }
-        Cmd::verify { file, address } => {
-            let binary = get_binary(file);
-            let bininfo = hf2::bin_info(&d).expect("bin_info failed");
-            log::debug!("{:?}", bininfo);
 
-            verify_bin(&binary, address, &bininfo, &d).unwrap();
-            println!("Success")
+        let d = match device_info.open_device(api) {
+            Ok(d) => d,
+            Err(e) => {
+                log::warn!("couldnt open {}: {}{}", summary, e, hint(device_info));
+                continue;
+            }
+        };
+        match filter.matches_device(&d) {
+            Ok(true) => matched.push((summary, d)),
+            Ok(false) => {}
+            Err(e) => log::debug!("couldnt probe {}: {:?}", summary, e),
         }
-        Cmd::elf { path } => {
-            let (binary, address) = elf_to_bin(path).unwrap();
+    }
 
-            let bininfo = hf2::bin_info(&d).expect("bin_info failed");
-            log::debug!("{:?}", bininfo);
+    pick(matched, style)
+}
 
-            flash_bin(&binary, address, &bininfo, &d).unwrap();
+/// The only device left, or an error naming all of them so the filter can be narrowed.
+fn pick<T>(mut matched: Vec<(DeviceSummary, T)>, style: &Style) -> Result<T, String> {
+    match matched.len() {
+        0 => Err("no device matches --select".into()),
+        1 => Ok(matched.remove(0).1),
+        n => {
+            let summaries: Vec<DeviceSummary> =
+                matched.into_iter().map(|(summary, _)| summary).collect();
+            Err(format!(
+                "--select matches {} devices, narrow it down:\n{}",
+                n,
+                device_table(&summaries).render(style).trim_end()
+            ))
         }
     }
 }
 
-fn info(d: &HidDevice) {
-    let info = hf2::info(&d).expect("info failed");
-    println!("{:?}", info);
+/// Devices as list shows them. Paths, then serials, then identities give up width in a narrow
+/// terminal, keeping their ends where devices differ.
+fn device_table(summaries: &[DeviceSummary]) -> Table {
+    let mut table = Table::new(&["DEVICE", "SERIAL", "USAGE PAGE", "INTERFACE", "PATH"])
+        .shrink(4)
+        .shrink(1)
+        .shrink(0);
+    for summary in summaries {
+        table.row(vec![
+            summary.identity.clone(),
+            summary.serial().unwrap_or("-").to_string(),
+            format!("0x{:04x}", summary.usage_page),
+            summary.interface.to_string(),
+            summary.path.clone(),
+        ]);
+    }
+    table
+}
+
+fn info(d: &HidDevice, style: &Style) {
+    let info = hf2::info(d).expect("info failed");
+    // INFO_UF2.TXT is `Key: value` lines after a first line naming the bootloader
+    let pairs: Vec<(&str, String)> = info
+        .info
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_once(": ") {
+            Some((key, value)) => (key.trim(), value.trim().to_string()),
+            None => ("Bootloader", line.to_string()),
+        })
+        .collect();
+    print!("{}", key_values(&pairs, style));
 }
 
 fn bininfo(d: &HidDevice) {
-    let bininfo = hf2::bin_info(&d).expect("bin_info failed");
+    let bininfo = hf2::bin_info(d).expect("bin_info failed");
     println!(
         "{:?} {:?}kb",
         bininfo,
@@ -88,9 +532,71 @@ fn bininfo(d: &HidDevice) {
     );
 }
 
+fn fingerprint(
+    d: &HidDevice,
+    address: u32,
+    pages: Option<u32>,
+    json: bool,
+    expect: Option<String>,
+    style: &Style,
+) {
+    let bininfo = hf2::bin_info(d).expect("bin_info failed");
+    log::debug!("{:?}", bininfo);
+
+    let pages = fingerprint_pages(address, pages, &bininfo).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    });
+
+    let fingerprint = flash_fingerprint(address, pages, &bininfo, d).unwrap();
+
+    if json {
+        println!(
+            "{{\"fingerprint\":\"{}\",\"address\":{},\"page_size\":{},\"checksums\":{:?}}}",
+            fingerprint, fingerprint.address, fingerprint.page_size, fingerprint.checksums
+        );
+    } else {
+        let pairs = [
+            ("fingerprint", fingerprint.to_string()),
+            ("address", format!("{:#010X}", fingerprint.address)),
+            ("pages", fingerprint.checksums.len().to_string()),
+            ("page size", fingerprint.page_size.to_string()),
+        ];
+        print!("{}", key_values(&pairs, style));
+    }
+
+    if let Some(expect) = expect {
+        if !expect.eq_ignore_ascii_case(&fingerprint.to_string()) {
+            eprintln!("expected {} but device has {}", expect, fingerprint);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Pages to fingerprint, pages if given otherwise the rest of flash assuming it starts at 0. None
+/// is an error, a fingerprint of nothing would match every device.
+fn fingerprint_pages(
+    address: u32,
+    pages: Option<u32>,
+    bininfo: &hf2::BinInfoResponse,
+) -> Result<u32, String> {
+    let pages = pages.unwrap_or_else(|| {
+        bininfo
+            .flash_num_pages
+            .saturating_sub(address.checked_div(bininfo.flash_page_size).unwrap_or(0))
+    });
+    if pages == 0 {
+        return Err(format!(
+            "no pages to fingerprint at {:#010X}, pass --pages or an address inside flash",
+            address
+        ));
+    }
+    Ok(pages)
+}
+
 fn dmesg(d: &HidDevice) {
     // todo, test. not supported on my board
-    let dmesg = hf2::dmesg(&d).expect("dmesg failed");
+    let dmesg = hf2::dmesg(d).expect("dmesg failed");
     println!("{:?}", dmesg);
 }
 
@@ -102,24 +608,69 @@ fn get_binary(file: PathBuf) -> Vec<u8> {
     binary
 }
 
-fn parse_hex_32(input: &str) -> Result<u32, std::num::ParseIntError> {
-    if input.starts_with("0x") {
-        u32::from_str_radix(&input[2..], 16)
+/// The check --sha256 or --verify-sig ask for, if any. Exits with 2 if it cant be read.
+fn integrity_check(
+    file: &Path,
+    sha256: Option<String>,
+    #[cfg(feature = "minisign")] verify_sig: Option<PathBuf>,
+) -> Option<IntegrityCheck> {
+    #[cfg(feature = "minisign")]
+    {
+        if let Some(public_key) = verify_sig {
+            let mut signature = file.as_os_str().to_owned();
+            signature.push(".minisig");
+            let check = IntegrityCheck::minisign_files(&public_key, Path::new(&signature));
+            return Some(check.unwrap_or_else(|e| integrity_failed(file.display(), e)));
+        }
+    }
+
+    let sha256 = sha256?;
+    let check = if Path::new(&sha256).is_file() {
+        IntegrityCheck::sha256_file(Path::new(&sha256))
     } else {
-        input.parse::<u32>()
+        IntegrityCheck::sha256_hex(&sha256)
+    };
+    Some(check.unwrap_or_else(|e| integrity_failed(file.display(), e)))
+}
+
+/// file, hashed as it is read.
+fn get_checked_binary(file: &Path, check: &IntegrityCheck) -> Vec<u8> {
+    let f = File::open(file).unwrap();
+    read_checked(f, check).unwrap_or_else(|e| integrity_failed(file.display(), e))
+}
+
+/// Exits with 2, image names the file or url that failed the check.
+fn integrity_failed(image: impl std::fmt::Display, e: IntegrityError) -> ! {
+    match e {
+        IntegrityError::Sha256Mismatch { expected, actual } => eprintln!(
+            "{} has SHA-256 {} not {}",
+            image,
+            hex(&actual),
+            hex(&expected)
+        ),
+        IntegrityError::BadSignature => {
+            eprintln!("{} isnt signed by that key", image)
+        }
+        IntegrityError::Malformed => eprintln!("the hash, key or signature doesnt parse"),
+        IntegrityError::File => eprintln!("couldnt read the hash, key, signature or binary"),
     }
+    std::process::exit(2);
 }
 
-fn parse_hex_16(input: &str) -> Result<u16, std::num::ParseIntError> {
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex_32(input: &str) -> Result<u32, std::num::ParseIntError> {
     if input.starts_with("0x") {
-        u16::from_str_radix(&input[2..], 16)
+        u32::from_str_radix(&input[2..], 16)
     } else {
-        input.parse::<u16>()
+        input.parse::<u32>()
     }
 }
 
 #[allow(non_camel_case_types)]
-#[derive(StructOpt, Debug, PartialEq)]
+#[derive(StructOpt, Debug)]
 pub enum Cmd {
     ///Reset the device into user-space app.
     resetIntoApp,
@@ -137,10 +688,35 @@ pub enum Cmd {
 
     /// flash binary, note includes a verify and reset into app
     flash {
+        #[cfg(not(feature = "http"))]
         #[structopt(short = "f", name = "file", long = "file")]
         file: PathBuf,
+        #[cfg(feature = "http")]
+        #[structopt(short = "f", name = "file", long = "file", required_unless = "url")]
+        file: Option<PathBuf>,
+        /// fetch the binary over http(s) instead of reading a file, exits with 3 if it cant be fetched. --sha256 checks it once downloaded
+        #[cfg(feature = "http")]
+        #[structopt(long = "url", conflicts_with = "file")]
+        url: Option<String>,
         #[structopt(short = "a", name = "address", long = "address", parse(try_from_str = parse_hex_32))]
         address: u32,
+        /// bytes at the start of the file to leave out, ex the bootloader of a combined image. address stays the address of the start of the file
+        #[structopt(name = "skip", long = "skip", default_value = "0", parse(try_from_str = parse_hex_32))]
+        skip: u32,
+        /// check the binary fits the device and checksum it without writing anything
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+        /// pad with 0xFF to the end of flash, erasing whatever was after the binary
+        #[structopt(long = "fill-to-end")]
+        fill_to_end: bool,
+        /// refuse the binary unless it has this SHA-256, in hex or a file as sha256sum writes them. exits with 2 if it doesnt match
+        #[structopt(long = "sha256")]
+        sha256: Option<String>,
+        /// refuse the binary unless <file>.minisig is a signature of it under this minisign public key. exits with 2 if it isnt
+        #[cfg(feature = "minisign")]
+        #[structopt(long = "verify-sig", conflicts_with = "sha256")]
+        #[cfg_attr(feature = "http", structopt(conflicts_with = "url"))]
+        verify_sig: Option<PathBuf>,
     },
 
     /// verify binary
@@ -149,12 +725,61 @@ pub enum Cmd {
         file: PathBuf,
         #[structopt(short = "a", name = "address", long = "address", parse(try_from_str = parse_hex_32))]
         address: u32,
+        /// bytes at the start of the file to leave out, ex the bootloader of a combined image. address stays the address of the start of the file
+        #[structopt(name = "skip", long = "skip", default_value = "0", parse(try_from_str = parse_hex_32))]
+        skip: u32,
     },
 
     /// flash elf, note includes a verify and reset into app
     elf {
         #[structopt(parse(from_os_str))]
         path: PathBuf,
+        #[structopt(flatten)]
+        arch: ArchArgs,
+    },
+
+    /// fingerprint what is in flash from the devices page checksums, to check devices run the same firmware without the binary at hand
+    fingerprint {
+        #[structopt(short = "a", name = "address", long = "address", parse(try_from_str = parse_hex_32))]
+        address: u32,
+        /// number of pages, defaults to the rest of flash
+        #[structopt(name = "pages", long = "pages", parse(try_from_str = parse_hex_32))]
+        pages: Option<u32>,
+        /// print the fingerprint with the page checksums it was made from as json
+        #[structopt(long = "json")]
+        json: bool,
+        /// exit with an error unless the device has this fingerprint
+        #[structopt(name = "expect", long = "expect")]
+        expect: Option<String>,
+    },
+
+    /// list connected devices using enumeration metadata only, without opening them
+    list,
+
+    /// flash the binary, then again each time it changes, until interrupted
+    #[cfg(feature = "watch")]
+    watch {
+        #[structopt(short = "f", name = "file", long = "file")]
+        file: PathBuf,
+        #[structopt(short = "a", name = "address", long = "address", parse(try_from_str = parse_hex_32))]
+        address: u32,
+        /// bytes at the start of the file to leave out, ex the bootloader of a combined image. address stays the address of the start of the file
+        #[structopt(name = "skip", long = "skip", default_value = "0", parse(try_from_str = parse_hex_32))]
+        skip: u32,
+    },
+
+    /// print udev rules letting you use every known board without root
+    #[structopt(name = "udev-rules")]
+    udev_rules {
+        /// write them to /etc/udev/rules.d/99-hf2.rules and reload udev, needs root
+        #[structopt(long = "install")]
+        install: bool,
+    },
+
+    /// print a shell completion script to stdout, ex `hf2 completions bash > /etc/bash_completion.d/hf2`
+    completions {
+        #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+        shell: Shell,
     },
 }
 
@@ -164,8 +789,175 @@ struct Opt {
     #[structopt(subcommand)]
     cmd: Cmd,
 
-    #[structopt(short = "p", name = "pid", long = "pid", parse(try_from_str = parse_hex_16))]
-    pid: Option<u16>,
-    #[structopt(short = "v", name = "vid", long = "vid", parse(try_from_str = parse_hex_16))]
-    vid: Option<u16>,
+    #[structopt(flatten)]
+    device: DeviceArgs,
+    /// pick the device matching all of a comma separated list of vid, pid, serial (prefix), family, board (Board-ID contains) and mode (bootloader or app), ! negates, ex family=SAMD51,mode=bootloader,!serial=ABC123
+    #[structopt(name = "select", long = "select")]
+    select: Option<DeviceFilter>,
+    /// lay output out without padding, rules, truncation or color, for screen readers
+    #[structopt(long = "plain")]
+    plain: bool,
+    /// auto, always or never. auto colors a terminal unless NO_COLOR is set
+    #[structopt(long = "color", default_value = "auto")]
+    color: ColorChoice,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completions_include_flags_and_subcommands() {
+        let mut script = vec![];
+        Opt::clap().gen_completions_to("hf2", Shell::Bash, &mut script);
+        let script = String::from_utf8(script).unwrap();
+
+        for expected in &["--vid", "--pid", "--file", "--address", "flash", "elf"] {
+            assert!(script.contains(expected), "missing {}", expected);
+        }
+    }
+
+    fn wide() -> Style {
+        Style {
+            color: false,
+            plain: false,
+            width: 200,
+        }
+    }
+
+    fn summary(vid: u16, pid: u16, interface: i32) -> DeviceSummary {
+        DeviceSummary {
+            vid,
+            pid,
+            serial: None,
+            identity: String::new(),
+            path: format!("{:04x}:{:04x}:{}", vid, pid, interface),
+            usage_page: hf2::HF2_USAGE_PAGE,
+            usage: hf2::HF2_USAGE,
+            interface,
+        }
+    }
+
+    #[test]
+    fn pick_needs_exactly_one() {
+        assert!(pick::<()>(vec![], &wide()).is_err());
+        assert_eq!(
+            pick(vec![(summary(0x239A, 0x003F, 0), 'a')], &wide()),
+            Ok('a')
+        );
+
+        let message = pick(
+            vec![
+                (summary(0x239A, 0x003F, 0), 'a'),
+                (summary(0x239A, 0x003F, 2), 'b'),
+            ],
+            &wide(),
+        )
+        .unwrap_err();
+        assert!(message.contains("2 devices"));
+        assert!(message.contains("239a:003f:0") && message.contains("239a:003f:2"));
+    }
+
+    #[test]
+    fn fingerprint_needs_a_page() {
+        let bininfo = hf2::BinInfoResponse::manual(256, 16);
+
+        assert_eq!(fingerprint_pages(0x400, None, &bininfo), Ok(12));
+        assert_eq!(fingerprint_pages(0x400, Some(2), &bininfo), Ok(2));
+        assert!(fingerprint_pages(0x400, Some(0), &bininfo).is_err());
+        assert!(fingerprint_pages(0x1000, None, &bininfo).is_err());
+        assert!(fingerprint_pages(0x8000, None, &bininfo).is_err());
+        // a device not reporting its page count leaves nothing to default to
+        assert!(fingerprint_pages(0, None, &hf2::BinInfoResponse::manual(256, 0)).is_err());
+    }
+
+    #[test]
+    fn blank_and_shared_serials() {
+        let with_serial = |serial: &str, path: &str| DeviceSummary {
+            serial: Some(serial.into()),
+            path: path.into(),
+            ..summary(0x239A, 0x003F, 0)
+        };
+        let mut devices = vec![
+            with_serial("", "/dev/hidraw0"),
+            with_serial("", "/dev/hidraw1"),
+            with_serial("0123", "/dev/hidraw2"),
+            with_serial("0123", "/dev/hidraw3"),
+            with_serial("4567", "/dev/hidraw4"),
+        ];
+        assign_identities(&mut devices);
+
+        let identities: Vec<&str> = devices.iter().map(|d| d.identity.as_str()).collect();
+        assert_eq!(
+            identities,
+            vec![
+                "239a:003f@/dev/hidraw0",
+                "239a:003f@/dev/hidraw1",
+                "239a:003f@/dev/hidraw2",
+                "239a:003f@/dev/hidraw3",
+                "239a:003f:4567",
+            ]
+        );
+        assert_eq!(devices[0].serial(), None);
+
+        // a blank serial never matches, the two sharing one are told apart by path
+        let filter: DeviceFilter = "serial=0123".parse().unwrap();
+        let matched: Vec<(DeviceSummary, usize)> = devices
+            .iter()
+            .cloned()
+            .zip(0..)
+            .filter(|(d, _)| filter.matches_ids(d.vid, d.pid, d.serial()))
+            .collect();
+        let message = pick(matched, &wide()).unwrap_err();
+        assert!(message.contains("@/dev/hidraw2") && message.contains("@/dev/hidraw3"));
+        assert!(!message.contains("hidraw0"));
+    }
+
+    #[test]
+    fn list_refuses_filters_it_cant_check() {
+        let cheap: DeviceFilter = "vid=239a,!serial=ABC".parse().unwrap();
+        assert!(list_filter(&cheap).is_ok());
+
+        let probed: DeviceFilter = "serial=0123,mode=bootloader,family=SAMD51,!mode=app"
+            .parse()
+            .unwrap();
+        let message = list_filter(&probed).unwrap_err();
+        assert!(message.contains("mode, family"), "{}", message);
+        assert!(!message.contains("select by serial"), "{}", message);
+    }
+
+    #[test]
+    fn list_filters_device_metadata() {
+        let vendor = vendor_map();
+        let devices = [
+            summary(0x239A, 0x003F, 0),
+            summary(0x239A, 0x003F, 2),
+            summary(0x046D, 0xC52B, 0),
+            summary(0x1209, 0x1234, 1),
+            // the keyboard interface of a composite bootloader
+            DeviceSummary {
+                usage_page: 0x0001,
+                usage: 0x0006,
+                ..summary(0x239A, 0x003F, 1)
+            },
+        ];
+
+        let select = |vid, pid, interface| -> Vec<DeviceSummary> {
+            devices
+                .iter()
+                .filter(|summary| summary.matches(vid, pid, interface, &vendor))
+                .cloned()
+                .collect()
+        };
+
+        // only known bootloaders without a vid/pid
+        assert_eq!(select(None, None, None), devices[..2].to_vec());
+        assert_eq!(select(None, None, Some(2)), devices[1..2].to_vec());
+        // any device once given explicitly
+        assert_eq!(
+            select(Some(0x1209), Some(0x1234), None),
+            devices[3..4].to_vec()
+        );
+        assert_eq!(select(Some(0x046D), None, None), devices[2..3].to_vec());
+    }
 }
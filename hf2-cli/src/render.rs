@@ -0,0 +1,276 @@
+use std::io::IsTerminal;
+use std::str::FromStr;
+
+/// Whether to color output, `--color`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    /// Color a terminal unless NO_COLOR is set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("{} isnt auto, always or never", s)),
+        }
+    }
+}
+
+/// How human readable output is laid out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Style {
+    pub color: bool,
+    /// One tab separated line per row and `key: value` pairs, no padding, rules, truncation or
+    /// color, for screen readers and narrow terminals.
+    pub plain: bool,
+    /// Columns tables are fit into.
+    pub width: usize,
+}
+
+impl Style {
+    /// The style the flags ask for, the width from COLUMNS or else 80.
+    pub fn new(color: ColorChoice, plain: bool) -> Self {
+        let color = match color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+            }
+        };
+        let width = std::env::var("COLUMNS")
+            .ok()
+            .and_then(|columns| columns.parse().ok())
+            .unwrap_or(80);
+
+        Style {
+            color: color && !plain,
+            plain,
+            width,
+        }
+    }
+
+    fn bold(&self, text: &str) -> String {
+        if self.color {
+            format!("\x1b[1m{}\x1b[0m", text)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// Columns of cells under a header row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+    shrink: Vec<usize>,
+}
+
+impl Table {
+    pub fn new(headers: &[&'static str]) -> Self {
+        Table {
+            headers: headers.to_vec(),
+            rows: vec![],
+            shrink: vec![],
+        }
+    }
+
+    /// Lets column give up width when the table is wider than the terminal, columns given first
+    /// shrinking first. Cells keep their end, where serials and paths differ, behind an ellipsis.
+    pub fn shrink(mut self, column: usize) -> Self {
+        self.shrink.push(column);
+        self
+    }
+
+    pub fn row(&mut self, cells: Vec<String>) {
+        self.rows.push(cells);
+    }
+
+    pub fn render(&self, style: &Style) -> String {
+        if style.plain {
+            return std::iter::once(self.headers.join("\t"))
+                .chain(self.rows.iter().map(|row| row.join("\t")))
+                .map(|line| line + "\n")
+                .collect();
+        }
+
+        let mut widths: Vec<usize> = self.headers.iter().map(|header| width(header)).collect();
+        for row in &self.rows {
+            for (column, cell) in row.iter().enumerate() {
+                widths[column] = widths[column].max(width(cell));
+            }
+        }
+
+        let gaps = 2 * widths.len().saturating_sub(1);
+        let mut excess = (widths.iter().sum::<usize>() + gaps).saturating_sub(style.width);
+        for &column in &self.shrink {
+            let least = width(self.headers[column]).max(MIN_WIDTH);
+            let give = excess.min(widths[column].saturating_sub(least));
+            widths[column] -= give;
+            excess -= give;
+        }
+
+        let line = |cells: Vec<String>, header: bool| {
+            let last = cells.len().saturating_sub(1);
+            let mut line = String::new();
+            for (column, cell) in cells.iter().enumerate() {
+                let cell = truncate(cell, widths[column]);
+                let padding = if column == last {
+                    0
+                } else {
+                    widths[column] - width(&cell) + 2
+                };
+                line.push_str(&if header { style.bold(&cell) } else { cell });
+                line.push_str(&" ".repeat(padding));
+            }
+            line + "\n"
+        };
+
+        let headers = self
+            .headers
+            .iter()
+            .map(|header| header.to_string())
+            .collect();
+        let mut out = line(headers, true);
+        out.push_str(&line(
+            widths.iter().map(|w| "─".repeat(*w)).collect(),
+            false,
+        ));
+        for row in &self.rows {
+            out.push_str(&line(row.clone(), false));
+        }
+        out
+    }
+}
+
+/// Narrowest a shrunk column gets, room for an ellipsis and a few characters.
+const MIN_WIDTH: usize = 6;
+
+/// Keys and their values, the keys lined up.
+pub fn key_values(pairs: &[(&str, String)], style: &Style) -> String {
+    let key_width = pairs.iter().map(|(key, _)| width(key)).max().unwrap_or(0);
+    pairs
+        .iter()
+        .map(|(key, value)| {
+            if style.plain {
+                format!("{}: {}\n", key, value)
+            } else {
+                let padding = " ".repeat(key_width - width(key) + 2);
+                format!("{}{}{}\n", style.bold(key), padding, value)
+            }
+        })
+        .collect()
+}
+
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// text cut to max characters by dropping its start for an ellipsis.
+fn truncate(text: &str, max: usize) -> String {
+    let chars = width(text);
+    if chars <= max {
+        return text.to_string();
+    }
+    let keep = max.saturating_sub(1);
+    std::iter::once('…')
+        .chain(text.chars().skip(chars - keep))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devices() -> Table {
+        let mut table = Table::new(&["DEVICE", "SERIAL", "PATH"])
+            .shrink(2)
+            .shrink(1);
+        table.row(vec![
+            "239a:003d".into(),
+            "5F2A9C0B50533254382E3120FF0B1A2E".into(),
+            "/dev/hidraw3".into(),
+        ]);
+        table.row(vec!["1209:805a".into(), "-".into(), "/dev/hidraw10".into()]);
+        table
+    }
+
+    fn style(width: usize) -> Style {
+        Style {
+            color: false,
+            plain: false,
+            width,
+        }
+    }
+
+    #[test]
+    fn table_at_two_widths() {
+        assert_eq!(
+            devices().render(&style(80)),
+            "DEVICE     SERIAL                            PATH\n\
+             ─────────  ────────────────────────────────  ─────────────\n\
+             239a:003d  5F2A9C0B50533254382E3120FF0B1A2E  /dev/hidraw3\n\
+             1209:805a  -                                 /dev/hidraw10\n"
+        );
+
+        // the path gives up what it can, then the serial keeps its end
+        assert_eq!(
+            devices().render(&style(40)),
+            "DEVICE     SERIAL                 PATH\n\
+             ─────────  ─────────────────────  ──────\n\
+             239a:003d  …3254382E3120FF0B1A2E  …draw3\n\
+             1209:805a  -                      …raw10\n"
+        );
+    }
+
+    #[test]
+    fn plain_and_color() {
+        let plain = Style {
+            plain: true,
+            ..style(40)
+        };
+        assert_eq!(
+            devices().render(&plain),
+            "DEVICE\tSERIAL\tPATH\n\
+             239a:003d\t5F2A9C0B50533254382E3120FF0B1A2E\t/dev/hidraw3\n\
+             1209:805a\t-\t/dev/hidraw10\n"
+        );
+
+        let pairs = [
+            ("fingerprint", "00c0ffee".to_string()),
+            ("pages", "4".into()),
+        ];
+        assert_eq!(
+            key_values(&pairs, &style(80)),
+            "fingerprint  00c0ffee\npages        4\n"
+        );
+        assert_eq!(
+            key_values(&pairs, &plain),
+            "fingerprint: 00c0ffee\npages: 4\n"
+        );
+
+        let color = Style {
+            color: true,
+            ..style(80)
+        };
+        assert!(devices()
+            .render(&color)
+            .starts_with("\x1b[1mDEVICE\x1b[0m  "));
+        assert_eq!(key_values(&pairs[1..], &color), "\x1b[1mpages\x1b[0m  4\n");
+    }
+
+    #[test]
+    fn truncation_keeps_the_end() {
+        assert_eq!(truncate("ABCDEFGH", 8), "ABCDEFGH");
+        assert_eq!(truncate("ABCDEFGH", 5), "…EFGH");
+        assert_eq!("auto".parse(), Ok(ColorChoice::Auto));
+        assert!("sometimes".parse::<ColorChoice>().is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const RULES_DIR: &str = "/etc/udev/rules.d";
+pub const RULES_FILE: &str = "99-hf2.rules";
+
+/// udev rules giving the logged in user access to the usb and hidraw nodes of every vendor, each
+/// vendor once however many products it has.
+pub fn rules(vendor: &HashMap<u16, Vec<u16>>) -> String {
+    let vids: BTreeSet<u16> = vendor.keys().copied().collect();
+
+    let mut rules = String::from("# hf2 bootloaders, generated by `hf2 udev-rules`\n");
+    for vid in vids {
+        rules.push_str(&format!(
+            "SUBSYSTEM==\"usb\", ATTRS{{idVendor}}==\"{:04x}\", MODE=\"0660\", TAG+=\"uaccess\"\n",
+            vid
+        ));
+        rules.push_str(&format!(
+            "KERNEL==\"hidraw*\", ATTRS{{idVendor}}==\"{:04x}\", MODE=\"0660\", TAG+=\"uaccess\"\n",
+            vid
+        ));
+    }
+    rules
+}
+
+/// Writes rules to RULES_FILE in dir, RULES_DIR outside of tests, returning where.
+pub fn install(rules: &str, dir: &Path) -> Result<PathBuf, String> {
+    let path = dir.join(RULES_FILE);
+    std::fs::write(&path, rules).map_err(|e| match e.kind() {
+        ErrorKind::PermissionDenied => format!(
+            "writing {} needs root, try `sudo hf2 udev-rules --install`",
+            path.display()
+        ),
+        _ => format!("couldnt write {}: {}", path.display(), e),
+    })?;
+    Ok(path)
+}
+
+/// Asks udev to pick up the new rules for devices already plugged in. Only warns if udevadm isnt
+/// there, a replug or reboot does the same.
+pub fn reload() {
+    for args in &[&["control", "--reload-rules"][..], &["trigger"][..]] {
+        match Command::new("udevadm").args(*args).status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => eprintln!("udevadm {} exited with {}", args.join(" "), status),
+            Err(_) => {
+                eprintln!("udevadm not found, replug your board for the rules to apply");
+                return;
+            }
+        }
+    }
+}
+
+/// Hint for opening the device at node failing, on linux usually missing udev rules. hidapi's
+/// errors dont keep the OS error, so the node is opened again to see if that was the reason.
+/// Backends whose paths arent device nodes, ex libusb, get no hint.
+pub fn open_hint(node: &Path) -> &'static str {
+    match std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(node)
+    {
+        Ok(_) => "",
+        Err(e) => permission_hint(&e),
+    }
+}
+
+fn permission_hint(error: &std::io::Error) -> &'static str {
+    if cfg!(target_os = "linux") && error.kind() == ErrorKind::PermissionDenied {
+        ", run `hf2 udev-rules --install` so you dont need root"
+    } else {
+        ""
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_rule_pair_per_vendor() {
+        let vendor = maplit::hashmap! {
+            0x239A => vec![0x003F, 0x0051],
+            0x1209 => vec![0x805A],
+        };
+
+        assert_eq!(
+            rules(&vendor),
+            "# hf2 bootloaders, generated by `hf2 udev-rules`\n\
+             SUBSYSTEM==\"usb\", ATTRS{idVendor}==\"1209\", MODE=\"0660\", TAG+=\"uaccess\"\n\
+             KERNEL==\"hidraw*\", ATTRS{idVendor}==\"1209\", MODE=\"0660\", TAG+=\"uaccess\"\n\
+             SUBSYSTEM==\"usb\", ATTRS{idVendor}==\"239a\", MODE=\"0660\", TAG+=\"uaccess\"\n\
+             KERNEL==\"hidraw*\", ATTRS{idVendor}==\"239a\", MODE=\"0660\", TAG+=\"uaccess\"\n"
+        );
+
+        let builtin = rules(&hf2::utils::vendor_map());
+        assert_eq!(
+            builtin.lines().count(),
+            1 + 2 * hf2::utils::vendor_map().len()
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn hint_only_for_permissions() {
+        // EACCES and ENOENT, as opening a hidraw node without the rules or that is gone gives
+        let denied = std::io::Error::from_raw_os_error(13);
+        assert_eq!(denied.kind(), ErrorKind::PermissionDenied);
+        assert!(permission_hint(&denied).contains("udev-rules"));
+        assert_eq!(permission_hint(&std::io::Error::from_raw_os_error(2)), "");
+
+        assert_eq!(open_hint(Path::new("/dev/hidraw-hf2-missing")), "");
+        assert_eq!(open_hint(Path::new("0001:0004:00")), "");
+    }
+
+    #[test]
+    fn installs_into_dir() {
+        let dir = std::env::temp_dir().join(format!("hf2-udev-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = install("rules\n", &dir).unwrap();
+
+        assert_eq!(path, dir.join(RULES_FILE));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "rules\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let error = install("rules\n", &dir).unwrap_err();
+        assert!(error.starts_with("couldnt write"), "{}", error);
+    }
+}
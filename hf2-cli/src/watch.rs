@@ -0,0 +1,203 @@
+use crate::{try_open, Opt};
+use hf2::utils::{flash_bin_with_options, slice_image, verify_bin, FlashOptions};
+use hidapi::{HidApi, HidDevice};
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::mpsc::channel;
+use std::time::{Duration, SystemTime};
+
+/// A change has to hold still this long before it is flashed, so a half written file from a build
+/// in progress isnt.
+const SETTLE: Duration = Duration::from_millis(200);
+
+/// How long a device running its app gets to come back as a bootloader.
+const BOOTLOADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Flashes file, then again every time it changes, until killed. The device is found again by its
+/// serial number after each flash resets it into the app, boards without one are found the way
+/// the other commands find them.
+pub fn watch(api: &mut HidApi, args: &Opt, file: &Path, address: u32, skip: u32) -> ! {
+    let serial = try_open(api, args)
+        .map(|d| d.get_serial_number_string().ok().flatten())
+        .unwrap_or_else(|message| {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        })
+        .filter(|serial| !serial.is_empty());
+
+    println!("watching {}", file.display());
+    let watched = on_change(file, SETTLE, || {
+        let now = humantime::format_rfc3339_seconds(SystemTime::now());
+        // a failed flash waits for the next change rather than retrying in a loop
+        match flash_once(api, args, serial.as_deref(), file, address, skip) {
+            Ok(summary) => println!("{} {}", now, summary),
+            Err(e) => eprintln!("{} {}", now, e),
+        }
+        true
+    });
+    if let Err(message) = watched {
+        eprintln!("{}", message);
+    }
+    std::process::exit(1);
+}
+
+/// Calls changed once straight away and then whenever file is written, created or renamed into
+/// place and has held still for settle, until it returns false. The directory is watched rather
+/// than the file, as linkers replace the file instead of writing into it.
+fn on_change(
+    file: &Path,
+    settle: Duration,
+    mut changed: impl FnMut() -> bool,
+) -> Result<(), String> {
+    let file = file
+        .canonicalize()
+        .map_err(|e| format!("couldnt find {}: {}", file.display(), e))?;
+    let dir = file.parent().ok_or("cant watch the root directory")?;
+
+    let (tx, rx) = channel();
+    let mut watcher =
+        notify::watcher(tx, settle).map_err(|e| format!("couldnt watch files: {}", e))?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .map_err(|e| format!("couldnt watch {}: {}", dir.display(), e))?;
+
+    if !changed() {
+        return Ok(());
+    }
+    for event in rx {
+        if touches(&event, &file) && !changed() {
+            return Ok(());
+        }
+    }
+    Err(format!("stopped getting changes to {}", file.display()))
+}
+
+/// Whether event leaves new contents at file.
+fn touches(event: &DebouncedEvent, file: &Path) -> bool {
+    match event {
+        DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => path == file,
+        DebouncedEvent::Rename(_, to) => to == file,
+        _ => false,
+    }
+}
+
+fn flash_once(
+    api: &mut HidApi,
+    args: &Opt,
+    serial: Option<&str>,
+    file: &Path,
+    address: u32,
+    skip: u32,
+) -> Result<String, String> {
+    let binary =
+        std::fs::read(file).map_err(|e| format!("couldnt read {}: {}", file.display(), e))?;
+    let (offset, binary) = slice_image(&binary, skip as usize);
+    let address = address
+        .checked_add(offset)
+        .ok_or("--skip moves the address past 32 bits")?;
+
+    let d = reopen(api, args, serial)?;
+    let bininfo = hf2::bin_info(&d).map_err(|e| format!("bin_info failed: {:?}", e))?;
+
+    if verify_bin(binary, address, &bininfo, &d).is_ok() {
+        hf2::reset_into_app(&d).map_err(|e| format!("reset failed: {:?}", e))?;
+        return Ok(format!("{} unchanged", file.display()));
+    }
+
+    let result = flash_bin_with_options(binary, address, &bininfo, &FlashOptions::default(), &d)
+        .map_err(|e| format!("flash failed: {:?}", e))?;
+    Ok(format!(
+        "flashed {}, {} pages {} bytes at {:#010X}",
+        file.display(),
+        result.pages_written,
+        result.bytes_transferred,
+        address
+    ))
+}
+
+/// The watched device in bootloader mode. Devices are listed again first, the one flashed last
+/// has reset since.
+fn reopen(api: &mut HidApi, args: &Opt, serial: Option<&str>) -> Result<HidDevice, String> {
+    api.refresh_devices()
+        .map_err(|e| format!("couldnt list devices: {}", e))?;
+
+    let serial = match serial {
+        Some(serial) => serial,
+        None => return try_open(api, args),
+    };
+    let d = api
+        .device_list()
+        .filter(|device_info| device_info.serial_number() == Some(serial))
+        .find_map(|device_info| device_info.open_device(api).ok())
+        .ok_or_else(|| format!("{} isnt plugged in", serial))?;
+
+    hf2::ensure_bootloader(api, d, BOOTLOADER_TIMEOUT)
+        .map_err(|e| format!("couldnt get {} into its bootloader: {:?}", serial, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::RecvTimeoutError;
+
+    #[test]
+    fn only_changes_to_the_file_count() {
+        let file = Path::new("/tmp/app.bin");
+        let other = Path::new("/tmp/other.bin");
+
+        assert!(touches(&DebouncedEvent::Write(file.into()), file));
+        assert!(touches(&DebouncedEvent::Create(file.into()), file));
+        assert!(touches(
+            &DebouncedEvent::Rename(other.into(), file.into()),
+            file
+        ));
+        assert!(!touches(&DebouncedEvent::Write(other.into()), file));
+        assert!(!touches(
+            &DebouncedEvent::Rename(file.into(), other.into()),
+            file
+        ));
+        assert!(!touches(&DebouncedEvent::Remove(file.into()), file));
+        assert!(!touches(&DebouncedEvent::NoticeWrite(file.into()), file));
+    }
+
+    #[test]
+    fn calls_back_on_start_and_each_settled_write() {
+        let dir = std::env::temp_dir().join(format!("hf2-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("app.bin");
+        std::fs::write(&file, [0_u8; 4]).unwrap();
+
+        let (tx, rx) = channel();
+        let watching = {
+            let file = file.clone();
+            std::thread::spawn(move || {
+                let mut calls = 0;
+                on_change(&file, Duration::from_millis(50), || {
+                    calls += 1;
+                    tx.send(calls).unwrap();
+                    calls < 3
+                })
+            })
+        };
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(1));
+        // another file in the same directory is ignored
+        std::fs::write(dir.join("other.bin"), [1_u8; 4]).unwrap();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(300)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        // a burst of writes settles into one call
+        for len in 1..5 {
+            std::fs::write(&file, vec![2_u8; len]).unwrap();
+        }
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(2));
+        // replaced the way a linker does it
+        std::fs::write(dir.join("app.tmp"), [3_u8; 4]).unwrap();
+        std::fs::rename(dir.join("app.tmp"), &file).unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(3));
+
+        assert_eq!(watching.join().unwrap(), Ok(()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
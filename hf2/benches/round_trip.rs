@@ -0,0 +1,118 @@
+//! Latency of a BININFO sized round trip against an in memory device, split into framing the
+//! command, sending it through xmit, parsing the response and the whole command, to tell
+//! framing costs from transport ones. `cargo bench -p hf2`
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use hf2::{
+    defragment, frame_command, frame_message, CommandResponse, Complete, Defrag, Error, ReadWrite,
+    MAX_PACKET_PAYLOAD,
+};
+use scroll::{Pread, LE};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+/// A BININFO response with tag 0, as bin_info sends, for a 512 byte page SAMD51.
+fn bininfo_response() -> Vec<u8> {
+    let mut response = vec![0x00, 0x00, 0x00, 0x00];
+    for field in &[1_u32, 512, 1024, 576, 0x5511_4460] {
+        response.extend_from_slice(&field.to_le_bytes());
+    }
+    response
+}
+
+/// Answers every command with the same prepared packets, queued once the command is reassembled.
+struct InMemoryHid {
+    response: Vec<Vec<u8>>,
+    incoming: RefCell<Defrag<[u8; 64]>>,
+    outgoing: RefCell<VecDeque<Vec<u8>>>,
+}
+
+impl InMemoryHid {
+    fn new(response: &[u8]) -> Self {
+        let mut packets = vec![];
+        frame_message(response, MAX_PACKET_PAYLOAD, |packet| {
+            packets.push(packet.to_vec());
+            Ok(())
+        })
+        .unwrap();
+        InMemoryHid {
+            response: packets,
+            incoming: RefCell::new(Defrag::new([0; 64])),
+            outgoing: RefCell::new(VecDeque::new()),
+        }
+    }
+}
+
+impl ReadWrite for InMemoryHid {
+    fn hf2_write(&self, data: &[u8]) -> Result<usize, Error> {
+        // data[0] is the report id
+        let packet = data.get(1..).ok_or(Error::Arguments)?;
+        if let Some(Complete::Message(_)) = defragment(packet, &mut self.incoming.borrow_mut())? {
+            self.outgoing
+                .borrow_mut()
+                .extend(self.response.iter().cloned());
+        }
+        Ok(data.len())
+    }
+
+    fn hf2_read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        match self.outgoing.borrow_mut().pop_front() {
+            Some(packet) => {
+                buf[..packet.len()].copy_from_slice(&packet);
+                Ok(packet.len())
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+/// Takes every report and never answers.
+struct NoOp;
+
+impl ReadWrite for NoOp {
+    fn hf2_write(&self, data: &[u8]) -> Result<usize, Error> {
+        Ok(data.len())
+    }
+
+    fn hf2_read(&self, _: &mut [u8]) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
+
+fn round_trip(c: &mut Criterion) {
+    let response = bininfo_response();
+
+    c.bench_function("serialize BININFO", |b| {
+        b.iter(|| {
+            let mut reports = Vec::with_capacity(1);
+            frame_command(black_box(0x0001), 0, &[], MAX_PACKET_PAYLOAD, |packet| {
+                let mut report = [0_u8; 65];
+                report[1..][..packet.len()].copy_from_slice(packet);
+                reports.push(report);
+                Ok(())
+            })
+            .unwrap();
+            reports
+        })
+    });
+
+    // building and handing over the reports, with a transport that costs nothing
+    c.bench_function("xmit BININFO", |b| {
+        b.iter(|| hf2::xmit_static::<65>(black_box(0x0001), 0, &[], &NoOp).unwrap())
+    });
+
+    c.bench_function("deserialize CommandResponse", |b| {
+        b.iter(|| {
+            black_box(&response[..])
+                .pread_with::<CommandResponse>(0, LE)
+                .unwrap()
+        })
+    });
+
+    let d = InMemoryHid::new(&response);
+    c.bench_function("bin_info round trip", |b| {
+        b.iter(|| hf2::bin_info(black_box(&d)).unwrap())
+    });
+}
+
+criterion_group!(benches, round_trip);
+criterion_main!(benches);
@@ -0,0 +1,65 @@
+//! Send a command hf2 has no function for, ex one a vendor added to its bootloader, and print the
+//! response data.
+//!
+//! `cargo run --example custom_command -- 0x239A 0x003D 0x8001 01020304`
+
+use hf2::{CommandResponse, CommandResponseStatus, Error, ReadWrite};
+
+const USAGE: &str =
+    "usage: custom_command <vid> <pid> <command id> [data], numbers and data in hex";
+
+/// Sends command id with data, already LE, and returns the response data. Any status but Success
+/// is an Error::CommandNotRecognized, as for the commands hf2 has functions for.
+pub fn call(d: &impl ReadWrite, id: u32, data: &[u8]) -> Result<Vec<u8>, Error> {
+    hf2::xmit_static::<65>(id, 0, data, d)?;
+
+    match hf2::rx(d)? {
+        CommandResponse {
+            status: CommandResponseStatus::Success,
+            data,
+            ..
+        } => Ok(data),
+        _ => Err(Error::CommandNotRecognized),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2)
+}
+
+fn parse_hex(arg: &str) -> u32 {
+    u32::from_str_radix(arg.trim_start_matches("0x"), 16).unwrap_or_else(|_| usage())
+}
+
+/// Two hex digits a byte, an odd digit left over is a usage error.
+fn parse_data(arg: &str) -> Vec<u8> {
+    (0..arg.len())
+        .step_by(2)
+        .map(|i| {
+            arg.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .unwrap_or_else(|| usage())
+        })
+        .collect()
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    if args.len() != 3 && args.len() != 4 {
+        usage();
+    }
+    let data = args.get(3).map(|data| parse_data(data)).unwrap_or_default();
+
+    let api = hidapi::HidApi::new().expect("Couldn't find system usb");
+    let d = api
+        .open(parse_hex(&args[0]) as u16, parse_hex(&args[1]) as u16)
+        .expect("Couldn't open device");
+
+    let response = call(&d, parse_hex(&args[2]), &data).unwrap();
+    println!("{:02X?}", response);
+}
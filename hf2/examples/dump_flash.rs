@@ -0,0 +1,42 @@
+//! Read a region of memory out to a file.
+//!
+//! `cargo run --example dump_flash -- 0x239A 0x003D 0x4000 0x1000 dump.bin`
+
+use hf2::utils::{read_memory, ReadOrder, UtilError};
+use hf2::{ByteLen, ReadWrite};
+
+const USAGE: &str = "usage: dump_flash <vid> <pid> <address> <len> <out.bin>, numbers in hex";
+
+/// Reads len bytes from address.
+pub fn dump(d: &impl ReadWrite, address: u32, len: usize) -> Result<Vec<u8>, UtilError> {
+    let bininfo = hf2::bin_info(d)?;
+
+    read_memory(address, ByteLen(len), ReadOrder::Ascending, &bininfo, d)
+}
+
+fn parse_hex(arg: &str) -> u32 {
+    u32::from_str_radix(arg.trim_start_matches("0x"), 16).unwrap_or_else(|_| {
+        eprintln!("{}", USAGE);
+        std::process::exit(2)
+    })
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    if args.len() != 5 {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
+
+    let api = hidapi::HidApi::new().expect("Couldn't find system usb");
+    let d = api
+        .open(parse_hex(&args[0]) as u16, parse_hex(&args[1]) as u16)
+        .expect("Couldn't open device");
+
+    let data = dump(&d, parse_hex(&args[2]), parse_hex(&args[3]) as usize).unwrap();
+    std::fs::write(&args[4], data).expect("Couldn't write dump");
+}
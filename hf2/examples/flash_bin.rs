@@ -0,0 +1,43 @@
+//! Flash a binary, verify it and reset into the app.
+//!
+//! `cargo run --example flash_bin -- 0x239A 0x003D blinky.bin 0x4000`
+
+use hf2::utils::{flash_bin_with_options, FlashOptions, FlashResult, UtilError};
+use hf2::ReadWrite;
+
+const USAGE: &str = "usage: flash_bin <vid> <pid> <file.bin> <address>, numbers in hex";
+
+/// Flashes binary at address with the geometry the device reports.
+pub fn flash(d: &impl ReadWrite, binary: &[u8], address: u32) -> Result<FlashResult, UtilError> {
+    let bininfo = hf2::bin_info(d)?;
+
+    flash_bin_with_options(binary, address, &bininfo, &FlashOptions::default(), d)
+}
+
+fn parse_hex(arg: &str) -> u32 {
+    u32::from_str_radix(arg.trim_start_matches("0x"), 16).unwrap_or_else(|_| {
+        eprintln!("{}", USAGE);
+        std::process::exit(2)
+    })
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    if args.len() != 4 {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
+
+    let api = hidapi::HidApi::new().expect("Couldn't find system usb");
+    let d = api
+        .open(parse_hex(&args[0]) as u16, parse_hex(&args[1]) as u16)
+        .expect("Couldn't open device");
+    let binary = std::fs::read(&args[2]).expect("Couldn't read binary");
+
+    let result = flash(&d, &binary, parse_hex(&args[3])).unwrap();
+    println!("wrote {} pages", result.pages_written);
+}
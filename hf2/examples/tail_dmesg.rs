@@ -0,0 +1,55 @@
+//! Follow the device log, printing what is new each second.
+//!
+//! `cargo run --example tail_dmesg -- 0x239A 0x003D`
+
+use hf2::{Error, ReadWrite};
+use std::io::Write;
+use std::time::Duration;
+
+const USAGE: &str = "usage: tail_dmesg <vid> <pid>, numbers in hex";
+
+/// Writes whatever the log has gained since seen to out and remembers it. A log shorter than seen
+/// wrapped or was cleared, so it is written whole.
+pub fn tail(d: &impl ReadWrite, seen: &mut String, out: &mut impl Write) -> Result<(), Error> {
+    let logs = hf2::dmesg(d)?.logs;
+
+    let new = match logs.strip_prefix(seen.as_str()) {
+        Some(new) => new,
+        None => logs.as_str(),
+    };
+    out.write_all(new.as_bytes())
+        .map_err(|_| Error::Transmission)?;
+
+    *seen = logs;
+    Ok(())
+}
+
+fn parse_hex(arg: &str) -> u16 {
+    u16::from_str_radix(arg.trim_start_matches("0x"), 16).unwrap_or_else(|_| {
+        eprintln!("{}", USAGE);
+        std::process::exit(2)
+    })
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    if args.len() != 2 {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
+
+    let api = hidapi::HidApi::new().expect("Couldn't find system usb");
+    let d = api
+        .open(parse_hex(&args[0]), parse_hex(&args[1]))
+        .expect("Couldn't open device");
+
+    let mut seen = String::new();
+    loop {
+        tail(&d, &mut seen, &mut std::io::stdout()).unwrap();
+        std::thread::sleep(Duration::from_secs(1));
+    }
+}
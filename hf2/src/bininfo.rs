@@ -1,9 +1,10 @@
 use crate::command::{rx, xmit, Command, CommandResponse, CommandResponseStatus};
-use crate::Error;
+use crate::{Error, ReadWrite};
 use core::convert::TryFrom;
+use core::fmt;
 use scroll::{ctx, Pread, LE};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BinInfoMode {
     //bootloader, and thus flashing of user-space programs is allowed
     Bootloader = 0x0001,
@@ -23,8 +24,26 @@ impl TryFrom<u32> for BinInfoMode {
     }
 }
 
+impl BinInfoMode {
+    ///Reads just the mode, the first field of a BININFO response, for callers that dont need the rest.
+    pub fn try_from_bininfo_bytes(data: &[u8]) -> Result<Self, Error> {
+        BinInfoMode::try_from(data.pread_with::<u32>(0, LE)?)
+    }
+}
+
 /// This command states the current mode of the device:
-pub fn bin_info(d: &hidapi::HidDevice) -> Result<BinInfoResponse, Error> {
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// let bininfo = hf2::bin_info(&d).unwrap();
+/// println!("{} pages of {} bytes", bininfo.flash_num_pages, bininfo.flash_page_size);
+/// # }
+/// ```
+pub fn bin_info(d: &impl ReadWrite) -> Result<BinInfoResponse, Error> {
     xmit(Command::new(0x0001, 0, vec![]), d)?;
 
     match rx(d) {
@@ -38,6 +57,80 @@ pub fn bin_info(d: &hidapi::HidDevice) -> Result<BinInfoResponse, Error> {
     }
 }
 
+///bin_info unless the caller already knows the geometry, for minimal bootloaders without BININFO.
+///Only when neither is available is this an Error::CommandNotRecognized.
+pub fn bin_info_or(
+    d: &impl ReadWrite,
+    manual: Option<BinInfoResponse>,
+) -> Result<BinInfoResponse, Error> {
+    match manual {
+        Some(bininfo) => Ok(bininfo),
+        None => bin_info(d),
+    }
+}
+
+///Whether the device is in bootloader mode, per BININFO. A device that doesnt recognize BININFO is
+///most likely running the application so that is false rather than an error.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// if !hf2::is_bootloader(&d).unwrap() {
+///     hf2::start_flash(&d).unwrap();
+/// }
+/// # }
+/// ```
+pub fn is_bootloader(d: &impl ReadWrite) -> Result<bool, Error> {
+    match bin_info(d) {
+        Ok(bininfo) => Ok(bininfo.is_bootloader()),
+        Err(Error::CommandNotRecognized) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+///addr rounded down to a multiple of page_size. A page_size of 0 leaves addr as is.
+pub fn align_down(addr: u32, page_size: u32) -> u32 {
+    addr - addr.checked_rem(page_size).unwrap_or(0)
+}
+
+///addr rounded up to a multiple of page_size, None if that is past u32::MAX or page_size is 0.
+pub fn align_up(addr: u32, page_size: u32) -> Option<u32> {
+    match addr.checked_rem(page_size)? {
+        0 => Some(addr),
+        remainder => addr.checked_add(page_size - remainder),
+    }
+}
+
+///addr is a multiple of page_size, never for a page_size of 0.
+pub fn is_aligned(addr: u32, page_size: u32) -> bool {
+    addr.checked_rem(page_size) == Some(0)
+}
+
+///A span of flash, length bytes from start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashRegion {
+    pub start: u32,
+    ///u64 as a region can run to the end of the address space.
+    pub length: u64,
+}
+
+impl FlashRegion {
+    ///addr is in the region.
+    pub fn contains(&self, addr: u32) -> bool {
+        addr >= self.start && u64::from(addr - self.start) < self.length
+    }
+
+    ///All length bytes from start are in the region. An empty range is when start is.
+    pub fn contains_range(&self, start: u32, length: u32) -> bool {
+        start >= self.start
+            && u64::from(start - self.start) + u64::from(length) <= self.length
+            && (length > 0 || self.contains(start))
+    }
+}
+
 ///Response to the bin_info command
 #[derive(Debug, PartialEq)]
 pub struct BinInfoResponse {
@@ -48,30 +141,196 @@ pub struct BinInfoResponse {
     pub family_id: Option<FamilyId>,
 }
 
-#[allow(non_camel_case_types)]
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub enum FamilyId {
-    ATSAMD21,
-    ATSAMD51,
-    NRF52840,
-    STM32F103,
-    STM32F401,
-    ATMEGA32,
-    CYPRESS_FX2,
-    UNKNOWN(u32),
+impl BinInfoResponse {
+    ///Geometry supplied by hand for a device that doesnt implement BININFO. Such a device is assumed
+    ///to be a bootloader, and max_message_size is the minimum HF2 allows, flash_page_size + 64.
+    pub fn manual(flash_page_size: u32, flash_num_pages: u32) -> Self {
+        BinInfoResponse {
+            mode: BinInfoMode::Bootloader,
+            flash_page_size,
+            flash_num_pages,
+            max_message_size: flash_page_size.saturating_add(64),
+            family_id: None,
+        }
+    }
+
+    ///mode is BinInfoMode::Bootloader.
+    pub fn is_bootloader(&self) -> bool {
+        self.mode == BinInfoMode::Bootloader
+    }
+
+    ///mode is BinInfoMode::User, the application is running.
+    pub fn is_application(&self) -> bool {
+        self.mode == BinInfoMode::User
+    }
+
+    ///align_down to flash_page_size.
+    pub fn align_down(&self, addr: u32) -> u32 {
+        align_down(addr, self.flash_page_size)
+    }
+
+    ///align_up to flash_page_size.
+    pub fn align_up(&self, addr: u32) -> Option<u32> {
+        align_up(addr, self.flash_page_size)
+    }
+
+    ///is_aligned to flash_page_size.
+    pub fn is_aligned(&self, addr: u32) -> bool {
+        is_aligned(addr, self.flash_page_size)
+    }
+
+    ///All of flash. BININFO doesnt say where flash starts so it is taken to start at 0, as it does
+    ///on SAMD and nRF parts.
+    pub fn flash_region(&self) -> FlashRegion {
+        FlashRegion {
+            start: 0,
+            length: self.total_flash_size(),
+        }
+    }
+
+    ///Start addresses of the pages holding any of start..end, from the page start is in up to the
+    ///one holding end - 1. An empty range has no pages, wherever it is. Error::Arguments if end is
+    ///before start, the range runs past flash_region or there is no page size.
+    pub fn pages_for_range(&self, start: u32, end: u32) -> Result<Vec<u32>, Error> {
+        if start == end {
+            return Ok(vec![]);
+        }
+        if self.flash_page_size == 0
+            || end < start
+            || !self.flash_region().contains_range(start, end - start)
+        {
+            return Err(Error::Arguments);
+        }
+
+        Ok((self.align_down(start)..end)
+            .step_by(self.flash_page_size as usize)
+            .collect())
+    }
+
+    ///flash_page_size * flash_num_pages, as u64 as it can exceed u32
+    pub fn total_flash_size(&self) -> u64 {
+        u64::from(self.flash_page_size) * u64::from(self.flash_num_pages)
+    }
 }
 
-impl From<u32> for FamilyId {
-    fn from(val: u32) -> Self {
-        match val {
-            0x68ed_2b88 => Self::ATSAMD21,
-            0x5511_4460 => Self::ATSAMD51,
-            0x1b57_745f => Self::NRF52840,
-            0x5ee2_1072 => Self::STM32F103,
-            0x5775_5a57 => Self::STM32F401,
-            0x1657_3617 => Self::ATMEGA32,
-            0x5a18_069b => Self::CYPRESS_FX2,
-            _ => Self::UNKNOWN(val),
+///Declares FamilyId from a table of each family and its id, so converting either way and the names
+///cant disagree.
+macro_rules! family_ids {
+    ($($family:ident = $id:literal,)*) => {
+        #[allow(non_camel_case_types)]
+        #[derive(Debug, Copy, Clone, PartialEq)]
+        pub enum FamilyId {
+            $($family,)*
+            UNKNOWN(u32),
+        }
+
+        impl From<u32> for FamilyId {
+            fn from(val: u32) -> Self {
+                match val {
+                    $($id => Self::$family,)*
+                    _ => Self::UNKNOWN(val),
+                }
+            }
+        }
+
+        impl From<FamilyId> for u32 {
+            fn from(family_id: FamilyId) -> u32 {
+                match family_id {
+                    $(FamilyId::$family => $id,)*
+                    FamilyId::UNKNOWN(val) => val,
+                }
+            }
+        }
+
+        impl FamilyId {
+            ///The family's name, as the variant is spelled, ex ATSAMD51. None for an unknown
+            ///family.
+            pub fn name(&self) -> Option<&'static str> {
+                match self {
+                    $(FamilyId::$family => Some(stringify!($family)),)*
+                    FamilyId::UNKNOWN(_) => None,
+                }
+            }
+        }
+    };
+}
+
+family_ids! {
+    ATSAMD21 = 0x68ed_2b88,
+    ATSAMD51 = 0x5511_4460,
+    NRF52840 = 0x1b57_745f,
+    STM32F103 = 0x5ee2_1072,
+    STM32F401 = 0x5775_5a57,
+    ATMEGA32 = 0x1657_3617,
+    CYPRESS_FX2 = 0x5a18_069b,
+}
+
+impl FamilyId {
+    ///Architecture of the core parts of this family run, None for families that arent Cortex-M or
+    ///that hf2 doesnt know.
+    pub fn cpu_arch(&self) -> Option<CpuArch> {
+        match self {
+            FamilyId::ATSAMD21 => Some(CpuArch::V6M),
+            FamilyId::ATSAMD51 => Some(CpuArch::V7EM),
+            FamilyId::NRF52840 => Some(CpuArch::V7EM),
+            FamilyId::STM32F103 => Some(CpuArch::V7M),
+            FamilyId::STM32F401 => Some(CpuArch::V7EM),
+            FamilyId::ATMEGA32 | FamilyId::CYPRESS_FX2 | FamilyId::UNKNOWN(_) => None,
+        }
+    }
+}
+
+///Cortex-M architecture, as an image is built for it or a device family runs it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuArch {
+    ///Cortex-M0 and M0+
+    V6M,
+    ///Cortex-M3
+    V7M,
+    ///Cortex-M4 and M7
+    V7EM,
+    ///Cortex-M23
+    V8MBase,
+    ///Cortex-M33
+    V8MMain,
+}
+
+impl CpuArch {
+    ///An image built for self runs on device. armv7e-m is armv7-m plus the DSP extension, so a
+    ///thumbv7m image runs on a Cortex-M4 too.
+    pub fn runs_on(self, device: CpuArch) -> bool {
+        self == device || (self == CpuArch::V7M && device == CpuArch::V7EM)
+    }
+}
+
+impl fmt::Display for CpuArch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CpuArch::V6M => "armv6-m (Cortex-M0/M0+)",
+            CpuArch::V7M => "armv7-m (Cortex-M3)",
+            CpuArch::V7EM => "armv7e-m (Cortex-M4/M7)",
+            CpuArch::V8MBase => "armv8-m.base (Cortex-M23)",
+            CpuArch::V8MMain => "armv8-m.main (Cortex-M33)",
+        };
+        f.write_str(name)
+    }
+}
+
+///Every field as the raw u32 the device sent, for debugging the protocol.
+impl fmt::LowerHex for BinInfoResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self.mode {
+            BinInfoMode::Bootloader => BinInfoMode::Bootloader as u32,
+            BinInfoMode::User => BinInfoMode::User as u32,
+        };
+        write!(
+            f,
+            "BinInfoResponse {{ mode: {:#010x}, flash_page_size: {:#010x}, flash_num_pages: {:#010x}, max_message_size: {:#010x}, family_id: ",
+            mode, self.flash_page_size, self.flash_num_pages, self.max_message_size
+        )?;
+        match self.family_id {
+            Some(family_id) => write!(f, "{:#010x} }}", u32::from(family_id)),
+            None => write!(f, "None }}"),
         }
     }
 }
@@ -111,3 +370,251 @@ impl<'a> ctx::TryFromCtx<'a, scroll::Endian> for BinInfoResponse {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::tests::DeviceSimulator;
+
+    #[test]
+    fn region_contains() {
+        let region = FlashRegion {
+            start: 0x4000,
+            length: 0x1000,
+        };
+        let addrs = [
+            (0x3FFF, false),
+            (0x4000, true),
+            (0x4FFF, true),
+            (0x5000, false),
+            (0, false),
+            (u32::MAX, false),
+        ];
+        for (addr, expected) in &addrs {
+            assert_eq!(region.contains(*addr), *expected, "{:#x}", addr);
+        }
+
+        let ranges = [
+            (0x4000, 0x1000, true),
+            (0x4800, 0x800, true),
+            (0x4800, 0x801, false),
+            (0x3FFF, 2, false),
+            (0x4000, 0, true),
+            (0x5000, 0, false),
+            (0x4FFF, u32::MAX, false),
+        ];
+        for (start, length, expected) in &ranges {
+            assert_eq!(
+                region.contains_range(*start, *length),
+                *expected,
+                "{:#x} {:#x}",
+                start,
+                length
+            );
+        }
+
+        // all of a 4GiB address space
+        let whole = FlashRegion {
+            start: 0,
+            length: 1 << 32,
+        };
+        assert!(whole.contains(u32::MAX));
+        assert!(whole.contains_range(1, u32::MAX));
+
+        let flash = BinInfoResponse::manual(0x100, 16).flash_region();
+        assert!(flash.contains(0xFFF) && !flash.contains(0x1000));
+    }
+
+    #[test]
+    fn pages_covering_a_range() {
+        let bininfo = BinInfoResponse::manual(0x100, 16);
+        let cases = [
+            (0x000, 0x100, Some(vec![0x000])),
+            (0x080, 0x101, Some(vec![0x000, 0x100])),
+            (0x100, 0x300, Some(vec![0x100, 0x200])),
+            (0xF00, 0x1000, Some(vec![0xF00])),
+            (0x200, 0x200, Some(vec![])),
+            (0x280, 0x280, Some(vec![])),
+            (0x1000, 0x1000, Some(vec![])),
+            (0x0FF, 0x1001, None),
+            (0x300, 0x200, None),
+        ];
+
+        for (start, end, expected) in &cases {
+            assert_eq!(
+                bininfo.pages_for_range(*start, *end).ok(),
+                *expected,
+                "{:#X}..{:#X}",
+                start,
+                end
+            );
+        }
+        assert!(BinInfoResponse::manual(0, 16)
+            .pages_for_range(0, 1)
+            .is_err());
+    }
+
+    #[test]
+    fn alignment() {
+        let page_sizes = [1_u32, 3, 64, 256, 1000, 4096, 0x8000_0000, u32::MAX];
+        let addrs = (0..4096_u32).chain(u32::MAX - 4096..=u32::MAX).chain(
+            [0x4000, 0x8000_0000, 0x8000_0001, 0xFFFF_F000]
+                .iter()
+                .copied(),
+        );
+
+        for addr in addrs {
+            for &page_size in &page_sizes {
+                // the same arithmetic in u64, where nothing wraps
+                let down = u64::from(addr) / u64::from(page_size) * u64::from(page_size);
+                let up = u64::from(addr).div_ceil(u64::from(page_size)) * u64::from(page_size);
+
+                assert_eq!(u64::from(align_down(addr, page_size)), down);
+                assert_eq!(align_up(addr, page_size).map(u64::from), {
+                    if up <= u64::from(u32::MAX) {
+                        Some(up)
+                    } else {
+                        None
+                    }
+                });
+                assert_eq!(is_aligned(addr, page_size), down == u64::from(addr));
+            }
+
+            assert_eq!(align_down(addr, 0), addr);
+            assert_eq!(align_up(addr, 0), None);
+            assert!(!is_aligned(addr, 0));
+        }
+
+        let bininfo = BinInfoResponse::manual(0x100, 16);
+        assert_eq!(bininfo.align_down(0x4123), 0x4100);
+        assert_eq!(bininfo.align_up(0x4123), Some(0x4200));
+        assert_eq!(bininfo.align_up(u32::MAX), None);
+        assert!(bininfo.is_aligned(0x4200) && !bininfo.is_aligned(0x4201));
+    }
+
+    #[test]
+    fn bin_info_round_trip() {
+        let device = DeviceSimulator::new().handle(0x0001, |_| {
+            [0x0000_0001_u32, 512, 1024, 1024, 0x5511_4460]
+                .iter()
+                .flat_map(|field| field.to_le_bytes().to_vec())
+                .collect()
+        });
+
+        let bininfo = bin_info(&device).unwrap();
+
+        assert_eq!(
+            bininfo,
+            BinInfoResponse {
+                mode: BinInfoMode::Bootloader,
+                flash_page_size: 512,
+                flash_num_pages: 1024,
+                max_message_size: 1024,
+                family_id: Some(FamilyId::ATSAMD51),
+            }
+        );
+    }
+
+    #[test]
+    fn lower_hex() {
+        let bininfo = BinInfoResponse {
+            mode: BinInfoMode::Bootloader,
+            flash_page_size: 256,
+            flash_num_pages: 1024,
+            max_message_size: 1024,
+            family_id: Some(FamilyId::NRF52840),
+        };
+
+        assert_eq!(
+            format!("{:x}", bininfo),
+            "BinInfoResponse { mode: 0x00000001, flash_page_size: 0x00000100, flash_num_pages: 0x00000400, max_message_size: 0x00000400, family_id: 0x1b57745f }"
+        );
+        assert!(format!("{:x}", BinInfoResponse::manual(256, 4)).ends_with("family_id: None }"));
+    }
+
+    #[test]
+    fn family_id_round_trip() {
+        let cases = [
+            (0x5511_4460, FamilyId::ATSAMD51, Some("ATSAMD51")),
+            (0x5a18_069b, FamilyId::CYPRESS_FX2, Some("CYPRESS_FX2")),
+            (0x1234_5678, FamilyId::UNKNOWN(0x1234_5678), None),
+        ];
+
+        for (id, family_id, name) in &cases {
+            assert_eq!(FamilyId::from(*id), *family_id);
+            assert_eq!(u32::from(*family_id), *id);
+            assert_eq!(family_id.name(), *name);
+        }
+    }
+
+    #[test]
+    fn mode_predicates() {
+        let bininfo = BinInfoResponse::manual(256, 4);
+        assert!(bininfo.is_bootloader());
+        assert!(!bininfo.is_application());
+
+        let app = BinInfoResponse {
+            mode: BinInfoMode::User,
+            ..bininfo
+        };
+        assert!(!app.is_bootloader());
+        assert!(app.is_application());
+    }
+
+    #[test]
+    fn bin_info_not_recognized() {
+        let device = DeviceSimulator::new();
+
+        assert!(matches!(
+            bin_info(&device),
+            Err(Error::CommandNotRecognized)
+        ));
+    }
+
+    #[test]
+    fn total_flash_size() {
+        let bininfo = BinInfoResponse {
+            mode: BinInfoMode::Bootloader,
+            flash_page_size: 64 * 1024,
+            flash_num_pages: 65536,
+            max_message_size: 1024,
+            family_id: None,
+        };
+
+        assert_eq!(bininfo.total_flash_size(), 4 * 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn is_bootloader_by_mode() {
+        let bininfo = |mode: u32| {
+            move |_: &[u8]| -> Vec<u8> {
+                [mode, 512, 1024, 1024]
+                    .iter()
+                    .flat_map(|field| field.to_le_bytes().to_vec())
+                    .collect()
+            }
+        };
+
+        let bootloader = DeviceSimulator::new().handle(0x0001, bininfo(1));
+        let user = DeviceSimulator::new().handle(0x0001, bininfo(2));
+        let app = DeviceSimulator::new();
+
+        assert!(is_bootloader(&bootloader).unwrap());
+        assert!(!is_bootloader(&user).unwrap());
+        assert!(!is_bootloader(&app).unwrap());
+    }
+
+    #[test]
+    fn mode_from_bininfo_bytes() {
+        assert_eq!(
+            BinInfoMode::try_from_bininfo_bytes(&[0x01, 0x00, 0x00, 0x00, 0x00, 0x02]).unwrap(),
+            BinInfoMode::Bootloader
+        );
+        assert_eq!(
+            BinInfoMode::try_from_bininfo_bytes(&[0x02, 0x00, 0x00, 0x00]).unwrap(),
+            BinInfoMode::User
+        );
+        assert!(BinInfoMode::try_from_bininfo_bytes(&[0x03, 0x00, 0x00, 0x00]).is_err());
+        assert!(BinInfoMode::try_from_bininfo_bytes(&[0x01, 0x00]).is_err());
+    }
+}
@@ -1,10 +1,22 @@
 use crate::command::{rx, xmit, Command, CommandResponse, CommandResponseStatus};
-use crate::Error;
+use crate::{is_aligned, Error, ReadWrite};
 use scroll::{ctx, Pread, Pwrite, LE};
+use std::collections::HashMap;
 
 ///Compute checksum of a number of pages. Maximum value for num_pages is max_message_size / 2 - 2. The checksum algorithm used is CRC-16-CCITT.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// let chk = hf2::checksum_pages(&d, 0x4000, 1).unwrap();
+/// dbg!(chk.checksums);
+/// # }
+/// ```
 pub fn checksum_pages(
-    d: &hidapi::HidDevice,
+    d: &impl ReadWrite,
     target_address: u32,
     num_pages: u32,
 ) -> Result<ChecksumPagesResponse, Error> {
@@ -27,12 +39,118 @@ pub fn checksum_pages(
     }
 }
 
+///checksum_pages over the bytes from start up to end, both page aligned, rather than a page count.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// let chk = hf2::checksum_pages_range(&d, 0x4000, 0x4400, 0x100).unwrap();
+/// assert_eq!(chk.checksums.len(), 4);
+/// # }
+/// ```
+pub fn checksum_pages_range(
+    d: &impl ReadWrite,
+    start: u32,
+    end: u32,
+    page_size: u32,
+) -> Result<ChecksumPagesResponse, Error> {
+    if end <= start || !is_aligned(start, page_size) || !is_aligned(end, page_size) {
+        return Err(Error::Arguments);
+    }
+    checksum_pages(d, start, (end - start) / page_size)
+}
+
+///Most pages one CHKSUM_PAGES call can ask for, so the response, 4 bytes of header plus 2 bytes per page, fits in a message.
+pub fn checksum_pages_limit(max_message_size: u32) -> u32 {
+    (max_message_size / 2).saturating_sub(2)
+}
+
+///Checksum any number of pages by splitting them into as many CHKSUM_PAGES calls as needed, concatenating the results.
+///Each call is limited to checksum_pages_limit pages. Some firmware buffers less than that and silently cuts the response short,
+///so a short response is taken as the devices real limit, later calls ask for no more than it returned and the missing pages are asked for again.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// let bininfo = hf2::bin_info(&d).unwrap();
+/// let chk = hf2::checksum_pages_batched(
+///     &d,
+///     0x4000,
+///     bininfo.flash_num_pages - 32,
+///     bininfo.flash_page_size,
+///     bininfo.max_message_size,
+/// ).unwrap();
+/// # }
+/// ```
+pub fn checksum_pages_batched(
+    d: &impl ReadWrite,
+    target_address: u32,
+    num_pages: u32,
+    flash_page_size: u32,
+    max_message_size: u32,
+) -> Result<ChecksumPagesResponse, Error> {
+    let mut max_pages = checksum_pages_limit(max_message_size);
+    if max_pages == 0 || flash_page_size == 0 {
+        return Err(Error::Arguments);
+    }
+
+    let mut checksums = Vec::with_capacity(num_pages as usize);
+    let mut done = 0;
+
+    while done < num_pages {
+        let pages = core::cmp::min(num_pages - done, max_pages);
+        let address = done
+            .checked_mul(flash_page_size)
+            .and_then(|offset| offset.checked_add(target_address))
+            .ok_or(Error::Arguments)?;
+
+        let mut chk = checksum_pages(d, address, pages)?;
+        chk.checksums.truncate(pages as usize);
+        let received = chk.checksums.len() as u32;
+        if received < pages {
+            log::debug!(
+                "asked for {} checksums, got {}, limiting batches to {}",
+                pages,
+                received,
+                received
+            );
+            max_pages = received;
+        }
+        checksums.extend_from_slice(&chk.checksums);
+
+        done += received;
+    }
+
+    Ok(ChecksumPagesResponse { checksums })
+}
+
 ///Response to the checksum_pages command
 #[derive(Debug, PartialEq)]
 pub struct ChecksumPagesResponse {
     pub checksums: Vec<u16>,
 }
 
+impl ChecksumPagesResponse {
+    ///Maps the start address of each page, counting from base_addr, to its checksum.
+    pub fn into_address_map(self, base_addr: u32, page_size: u32) -> HashMap<u32, u16> {
+        (0_u32..)
+            .zip(self.checksums)
+            .map(|(page, checksum)| {
+                (
+                    base_addr.wrapping_add(page.wrapping_mul(page_size)),
+                    checksum,
+                )
+            })
+            .collect()
+    }
+}
+
 impl<'a> ctx::TryFromCtx<'a, scroll::Endian> for ChecksumPagesResponse {
     type Error = Error;
     fn try_from_ctx(this: &'a [u8], le: scroll::Endian) -> Result<(Self, usize), Self::Error> {
@@ -48,3 +166,130 @@ impl<'a> ctx::TryFromCtx<'a, scroll::Endian> for ChecksumPagesResponse {
         Ok((ChecksumPagesResponse { checksums }, offset))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::tests::{CapturingMock, DeviceSimulator};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn request_and_response_layout() {
+        let mock = CapturingMock::with_responses(vec![vec![
+            0x48, 0x00, 0x00, 0x00, 0x00, 0x34, 0x12, 0x78, 0x56,
+        ]]);
+
+        let chk = checksum_pages(&mock, 0x4000, 2).unwrap();
+
+        assert_eq!(
+            mock.captured(),
+            vec![vec![
+                0x00, 0x50, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00,
+                0x02, 0x00, 0x00, 0x00,
+            ]]
+        );
+        assert_eq!(chk.checksums, vec![0x1234, 0x5678]);
+    }
+
+    #[test]
+    fn range_to_page_count() {
+        let mock = CapturingMock::with_responses(vec![vec![
+            0x48, 0x00, 0x00, 0x00, 0x00, 0x34, 0x12, 0x78, 0x56,
+        ]]);
+
+        let chk = checksum_pages_range(&mock, 0x4000, 0x4200, 0x100).unwrap();
+
+        assert_eq!(
+            mock.captured()[0][10..18],
+            [0x00, 0x40, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(chk.checksums, vec![0x1234, 0x5678]);
+
+        for (start, end) in &[
+            (0x4000, 0x4000),
+            (0x4200, 0x4000),
+            (0x4010, 0x4200),
+            (0x4000, 0x4210),
+        ] {
+            assert!(matches!(
+                checksum_pages_range(&mock, *start, *end, 0x100),
+                Err(Error::Arguments)
+            ));
+        }
+        assert!(matches!(
+            checksum_pages_range(&mock, 0x4000, 0x4200, 0),
+            Err(Error::Arguments)
+        ));
+    }
+
+    #[test]
+    fn batched_respects_message_size() {
+        let calls = Rc::new(Cell::new(0));
+        let handler_calls = calls.clone();
+
+        // max_message_size of 16 leaves room for 6 checksums per response
+        let device = DeviceSimulator::new().handle(0x0007, move |data| {
+            let target_address = data.pread_with::<u32>(0, LE).unwrap();
+            let num_pages = data.pread_with::<u32>(4, LE).unwrap();
+            assert!(num_pages <= 6);
+            handler_calls.set(handler_calls.get() + 1);
+
+            // checksum each page as its page number
+            (0..num_pages)
+                .flat_map(|page| {
+                    ((target_address / 0x100 + page) as u16)
+                        .to_le_bytes()
+                        .to_vec()
+                })
+                .collect()
+        });
+
+        let chk = checksum_pages_batched(&device, 0x4000, 15, 0x100, 16).unwrap();
+
+        assert_eq!(calls.get(), 3);
+        assert_eq!(chk.checksums, (0x40..0x40 + 15).collect::<Vec<u16>>());
+    }
+
+    #[test]
+    fn batched_splits_after_truncation() {
+        let requested = Rc::new(std::cell::RefCell::new(vec![]));
+        let handler_requested = requested.clone();
+
+        // room for 6 checksums per response going by max_message_size, but the device only buffers 4
+        let device = DeviceSimulator::new().handle(0x0007, move |data| {
+            let target_address = data.pread_with::<u32>(0, LE).unwrap();
+            let num_pages = data.pread_with::<u32>(4, LE).unwrap();
+            handler_requested.borrow_mut().push(num_pages);
+
+            (0..num_pages.min(4))
+                .flat_map(|page| {
+                    ((target_address / 0x100 + page) as u16)
+                        .to_le_bytes()
+                        .to_vec()
+                })
+                .collect()
+        });
+
+        let chk = checksum_pages_batched(&device, 0x4000, 15, 0x100, 16).unwrap();
+
+        assert_eq!(*requested.borrow(), vec![6, 4, 4, 3]);
+        assert_eq!(chk.checksums, (0x40..0x40 + 15).collect::<Vec<u16>>());
+        assert_eq!(checksum_pages_limit(16), 6);
+        assert_eq!(checksum_pages_limit(2), 0);
+    }
+
+    #[test]
+    fn address_map() {
+        let response = ChecksumPagesResponse {
+            checksums: vec![0x1111, 0x2222, 0x3333],
+        };
+
+        let map = response.into_address_map(0x4000, 0x100);
+
+        assert_eq!(map.len(), 3);
+        assert_eq!(map[&0x4000], 0x1111);
+        assert_eq!(map[&0x4100], 0x2222);
+        assert_eq!(map[&0x4200], 0x3333);
+    }
+}
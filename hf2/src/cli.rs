@@ -0,0 +1,159 @@
+//! Command line flags hf2-cli and cargo-hf2 share, flattened into each with
+//! `#[structopt(flatten)]` so both spell, document and parse them the same.
+//!
+//! There are no short forms, cargo-hf2 forwards its other arguments to cargo build where `-p` is
+//! `--package`.
+
+use structopt::StructOpt;
+
+// Which device to talk to. Not a doc comment, structopt would make it the about of every app
+// this is flattened into.
+#[derive(Debug, Clone, Default, PartialEq, StructOpt)]
+pub struct DeviceArgs {
+    /// USB product id, decimal or 0x hex
+    #[structopt(name = "pid", long = "pid", parse(try_from_str = parse_hex_16))]
+    pub pid: Option<u16>,
+    /// USB vendor id, decimal or 0x hex
+    #[structopt(name = "vid", long = "vid", parse(try_from_str = parse_hex_16))]
+    pub vid: Option<u16>,
+    /// HID interface number, for composite devices exposing more than one HID interface
+    #[structopt(name = "interface", long = "interface")]
+    pub interface: Option<i32>,
+}
+
+impl DeviceArgs {
+    /// Each flag and whether it takes a value.
+    const FLAGS: &'static [(&'static str, bool)] =
+        &[("--pid", true), ("--vid", true), ("--interface", true)];
+
+    /// Removes these flags and their values from args, ex before the rest go to cargo build.
+    pub fn remove_from(args: &mut Vec<String>) {
+        remove_flags(args, Self::FLAGS)
+    }
+}
+
+// Whether to flash an elf built for another architecture than the device runs.
+#[derive(Debug, Clone, Default, PartialEq, StructOpt)]
+pub struct ArchArgs {
+    /// flash even if the elf is built for a different architecture than the device family runs
+    #[structopt(long = "force-arch")]
+    pub force_arch: bool,
+}
+
+impl ArchArgs {
+    const FLAGS: &'static [(&'static str, bool)] = &[("--force-arch", false)];
+
+    /// Removes these flags from args, ex before the rest go to cargo build.
+    pub fn remove_from(args: &mut Vec<String>) {
+        remove_flags(args, Self::FLAGS)
+    }
+}
+
+/// A u16 in decimal, or hex with a leading 0x.
+pub fn parse_hex_16(input: &str) -> Result<u16, std::num::ParseIntError> {
+    match input.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => input.parse::<u16>(),
+    }
+}
+
+/// Removes every flag in flags from args, along with the value following it for one that takes a
+/// value. `--flag=value` is one argument.
+fn remove_flags(args: &mut Vec<String>, flags: &[(&str, bool)]) {
+    let mut i = 0;
+    while let Some(arg) = args.get(i) {
+        let flag = flags.iter().find(|(flag, _)| {
+            arg == flag
+                || arg
+                    .strip_prefix(flag)
+                    .is_some_and(|rest| rest.starts_with('='))
+        });
+        match flag {
+            Some((flag, true)) if arg == flag => {
+                args.drain(i..args.len().min(i + 2));
+            }
+            Some(_) => {
+                args.remove(i);
+            }
+            None => i += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every long flag the help of T lists, but --help and --version.
+    fn long_flags<T: StructOpt>() -> Vec<String> {
+        let mut help = vec![];
+        T::clap().write_help(&mut help).unwrap();
+        String::from_utf8(help)
+            .unwrap()
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|word| word.starts_with("--") && *word != "--help" && *word != "--version")
+            .map(|word| word.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn flags_match_the_structs() {
+        let listed = |flags: &[(&str, bool)]| -> Vec<String> {
+            let mut flags: Vec<String> = flags.iter().map(|(flag, _)| flag.to_string()).collect();
+            flags.sort();
+            flags
+        };
+        let mut device = long_flags::<DeviceArgs>();
+        device.sort();
+        let mut arch = long_flags::<ArchArgs>();
+        arch.sort();
+
+        assert_eq!(device, listed(DeviceArgs::FLAGS));
+        assert_eq!(arch, listed(ArchArgs::FLAGS));
+    }
+
+    #[test]
+    fn removes_flags_and_their_values() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        let mut forwarded = args(&[
+            "--release",
+            "--vid",
+            "0x239A",
+            "--pid=0x003D",
+            "--force-arch",
+            "--interface",
+            "2",
+            "--features",
+            "vid",
+            "--pidx",
+        ]);
+
+        DeviceArgs::remove_from(&mut forwarded);
+        ArchArgs::remove_from(&mut forwarded);
+
+        assert_eq!(
+            forwarded,
+            args(&["--release", "--features", "vid", "--pidx"])
+        );
+
+        // a flag missing its value at the end
+        let mut truncated = args(&["--release", "--vid"]);
+        DeviceArgs::remove_from(&mut truncated);
+        assert_eq!(truncated, args(&["--release"]));
+    }
+
+    #[test]
+    fn parses_decimal_and_hex() {
+        let parsed = DeviceArgs::from_iter_safe(&["hf2", "--vid", "0x239A", "--pid", "61"]);
+
+        assert_eq!(
+            parsed.unwrap(),
+            DeviceArgs {
+                vid: Some(0x239A),
+                pid: Some(61),
+                interface: None,
+            }
+        );
+        assert!(parse_hex_16("0xZZ").is_err());
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::{Duration, Instant};
+
+///Time as seen by code that waits on the device, so tests can step it instead of sleeping.
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+///The wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    ///A clock that only moves when slept on.
+    pub(crate) struct MockClock {
+        start: Instant,
+        elapsed: Cell<Duration>,
+    }
+
+    impl MockClock {
+        pub(crate) fn new() -> Self {
+            MockClock {
+                start: Instant::now(),
+                elapsed: Cell::new(Duration::ZERO),
+            }
+        }
+
+        pub(crate) fn elapsed(&self) -> Duration {
+            self.elapsed.get()
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            self.start + self.elapsed.get()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.elapsed.set(self.elapsed.get() + duration);
+        }
+    }
+}
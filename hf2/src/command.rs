@@ -1,5 +1,11 @@
-use crate::{Error, ReadWrite};
+// everything in here handles bytes straight from the device, so out of bounds access has to be an
+// Error rather than a panic
+#![deny(clippy::indexing_slicing)]
+
+use crate::{Clock, Error, ReadWrite, SystemClock};
 use core::convert::TryFrom;
+use core::fmt;
+use std::time::Duration;
 
 use scroll::{ctx, Pread, Pwrite, LE};
 
@@ -21,19 +27,102 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Arguments => write!(f, "invalid arguments"),
+            Error::Parse => write!(f, "couldnt parse the response"),
+            Error::CommandNotRecognized => write!(f, "command not recognized"),
+            Error::Execution => write!(f, "command failed on the device"),
+            Error::Sequence => write!(f, "response tag didnt match the command"),
+            Error::Transmission => write!(f, "usb transmission failed"),
+            Error::ResponseTooShort { received } => {
+                write!(f, "response of {} bytes is too short", received)
+            }
+            Error::Timeout => write!(f, "timed out waiting on the device"),
+            Error::ShortWrite { expected, wrote } => {
+                write!(f, "wrote {} of {} report bytes", wrote, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+///An Error with the command it happened in, as Error alone cant say which of the commands of ex a
+///flash failed. The utils flash and verify functions return it as UtilError::Protocol.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// let api = hidapi::HidApi::new().unwrap();
+/// let d = api.open(0x239A, 0x003D).unwrap();
+///
+/// let bininfo = hf2::bin_info(&d).map_err(|e| hf2::ProtocolError::new(0x0001, e));
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ProtocolError {
+    pub command_id: u32,
+    ///ex BININFO, or UNKNOWN for ids outside the HF2 spec.
+    pub command_name: &'static str,
+    pub source: Error,
+}
+
+impl ProtocolError {
+    pub fn new(command_id: u32, source: Error) -> Self {
+        let command_name = match command_id {
+            0x0001 => "BININFO",
+            0x0002 => "INFO",
+            0x0003 => "RESET INTO APP",
+            0x0004 => "RESET INTO BOOTLOADER",
+            0x0005 => "START FLASH",
+            0x0006 => "WRITE FLASH PAGE",
+            0x0007 => "CHKSUM PAGES",
+            0x0008 => "READ WORDS",
+            0x0009 => "WRITE WORDS",
+            0x0010 => "DMESG",
+            _ => "UNKNOWN",
+        };
+        ProtocolError {
+            command_id,
+            command_name,
+            source,
+        }
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:#06x}): {}",
+            self.command_name, self.command_id, self.source
+        )
+    }
+}
+
+impl std::error::Error for ProtocolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+///A response as received, for commands hf2 has no function for, ex vendor specific ones.
 #[derive(Debug, PartialEq)]
-pub(crate) struct CommandResponse {
+pub struct CommandResponse {
     ///arbitrary number set by the host, for example as sequence number. The response should repeat the tag.
-    pub(crate) tag: u16,
-    pub(crate) status: CommandResponseStatus, //    uint8_t status;
+    pub tag: u16,
+    pub status: CommandResponseStatus, //    uint8_t status;
     ///additional information In case of non-zero status
-    pub(crate) status_info: u8, // optional?
+    pub status_info: u8, // optional?
     ///LE bytes
-    pub(crate) data: Vec<u8>,
+    pub data: Vec<u8>,
 }
 
-#[derive(Debug, PartialEq)]
-pub(crate) enum CommandResponseStatus {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandResponseStatus {
     //command understood and executed correctly
     Success = 0x00,
     //command not understood
@@ -55,15 +144,16 @@ impl TryFrom<u8> for CommandResponseStatus {
     }
 }
 
-#[derive(Debug, PartialEq)]
-enum PacketType {
-    //Inner packet of a command message
+///Type of an HF2 packet, the top 2 bits of its header byte. The discriminants are those bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    ///Inner packet of a command message
     Inner = 0,
-    //Final packet of a command message
+    ///Final packet of a command message
     Final = 1,
-    //Serial stdout
+    ///Serial stdout
     StdOut = 2,
-    //Serial stderr
+    ///Serial stderr
     Stderr = 3,
 }
 
@@ -101,7 +191,7 @@ impl<'a> ctx::TryFromCtx<'a, scroll::Endian> for CommandResponse {
                 tag,
                 status,
                 status_info,
-                data: this[offset..].to_vec(),
+                data: this.get(offset..).ok_or(Error::Parse)?.to_vec(),
             },
             offset,
         ))
@@ -137,67 +227,150 @@ impl Command {
 pub(crate) fn xmit(cmd: Command, d: &impl ReadWrite) -> Result<(), Error> {
     log::debug!("{:?}", cmd);
 
-    //Packets are up to 64 bytes long + first byte is Report ID,
-    let buffer = &mut [0_u8; 65];
-
-    // Report ID at 0, hardcoded to 0, header at 1 filled in later, so start at 2
-    let mut offset = 2;
-
-    //command struct is 8 bytes
-    buffer.gwrite_with(cmd.id, &mut offset, LE)?;
-    buffer.gwrite_with(cmd.tag, &mut offset, LE)?;
-    buffer.gwrite_with(cmd._reserved0, &mut offset, LE)?;
-    buffer.gwrite_with(cmd._reserved1, &mut offset, LE)?;
-
-    //copy up to the first 55 bytes
-    let mut count = if cmd.data.len() > 55 {
-        55
-    } else {
-        cmd.data.len()
-    };
-    buffer.gwrite(&cmd.data[..count], &mut offset)?;
+    //Packets are up to 64 bytes long + first byte is Report ID
+    xmit_static::<65>(cmd.id, cmd.tag, &cmd.data, d)
+}
 
-    //subtract header from offset for packet size
-    if count == cmd.data.len() {
-        buffer[1] = (PacketType::Final as u8) << 6 | (offset - 2) as u8;
-        log::debug!("tx: {:02X?}", &buffer[..offset]);
+///Transmit a command without allocating, for hosts without a heap. BUF is the size of the stack
+///buffer each report is built in, report ID and packet header included, so packets carry up to
+///BUF - 2 bytes and never more than the 63 HF2 allows. The 8 byte command header has to fit in the
+///first packet, a smaller BUF is an Error::Arguments. data should already be LE.
+pub fn xmit_static<const BUF: usize>(
+    id: u32,
+    tag: u16,
+    data: &[u8],
+    d: &impl ReadWrite,
+) -> Result<(), Error> {
+    frame_command(
+        id,
+        tag,
+        data,
+        BUF.saturating_sub(2),
+        report_writer::<BUF>(0, d),
+    )
+}
 
-        return d.hf2_write(&buffer[..offset]).map(|_| ());
-    } else {
-        buffer[1] = (PacketType::Inner as u8) << 6 | (offset - 2) as u8;
-        log::debug!("tx: {:02X?}", &buffer[..offset]);
+///xmit for devices that take HF2 on a report ID other than 0. The ID replaces the 0 that normally
+///leads each report, it has its own byte so packets still carry up to 63 bytes.
+pub fn xmit_with_report_id(
+    report_id: u8,
+    id: u32,
+    tag: u16,
+    data: &[u8],
+    d: &impl ReadWrite,
+) -> Result<(), Error> {
+    frame_command(id, tag, data, 63, report_writer::<65>(report_id, d))
+}
 
-        d.hf2_write(&buffer[..offset])?;
+///Transmit a command already serialized in the HF2 command format, the 8 byte header of id, tag
+///and the reserved bytes followed by the data, ex to replay a captured trace. The bytes are framed
+///as they are, reserved bytes included. Anything shorter than the header is an Error::Arguments.
+pub fn xmit_bytes(raw_command: &[u8], d: &impl ReadWrite) -> Result<(), Error> {
+    if raw_command.len() < 8 {
+        return Err(Error::Arguments);
     }
+    log::debug!("xmit raw: {:02X?}", raw_command);
 
-    //send the rest in chunks up to 63
-    for chunk in cmd.data[count..].chunks(63) {
-        count += chunk.len();
+    frame_message(raw_command, 63, report_writer::<65>(0, d))
+}
 
-        if count == cmd.data.len() {
-            buffer[1] = (PacketType::Final as u8) << 6 | chunk.len() as u8;
-        } else {
-            buffer[1] = (PacketType::Inner as u8) << 6 | chunk.len() as u8;
-        }
+///Writes each packet it is called with as a report led by report_id, built in a BUF sized buffer.
+fn report_writer<const BUF: usize>(
+    report_id: u8,
+    d: &impl ReadWrite,
+) -> impl FnMut(&[u8]) -> Result<(), Error> + '_ {
+    let mut buffer = [0_u8; BUF];
+
+    move |packet| {
+        let report = buffer.get_mut(..=packet.len()).ok_or(Error::Arguments)?;
+        let (id, rest) = report.split_first_mut().ok_or(Error::Arguments)?;
+        *id = report_id;
+        rest.copy_from_slice(packet);
+
+        log::debug!("tx: {:02X?}", report);
+        write_report(d, report)
+    }
+}
 
-        buffer[2..(chunk.len() + 2)].copy_from_slice(chunk);
+///Send data as serial StdOut packets. Unlike commands each packet stands on its own, there is no
+///Inner/Final sequence and no response.
+pub(crate) fn xmit_stdout(data: &[u8], d: &impl ReadWrite) -> Result<(), Error> {
+    frame_serial(data, PacketType::StdOut, report_writer::<65>(0, d))
+}
 
-        log::debug!("tx: {:02X?}", &buffer[..(chunk.len() + 2)]);
-        d.hf2_write(&buffer[..(chunk.len() + 2)])?;
+///A report only partly written leaves the device with a truncated packet, so that is an error.
+///Writing more is fine, some hidapi backends pad reports up to the report length.
+fn write_report(d: &impl ReadWrite, report: &[u8]) -> Result<(), Error> {
+    let wrote = d.hf2_write(report)?;
+    if wrote < report.len() {
+        return Err(Error::ShortWrite {
+            expected: report.len(),
+            wrote,
+        });
     }
     Ok(())
 }
 
-///Receive a CommandResponse, CommandResponse.data is not interpreted in any way.
-pub(crate) fn rx(d: &impl ReadWrite) -> Result<CommandResponse, Error> {
-    let mut bitsnbytes: Vec<u8> = vec![];
+///Receive a CommandResponse, CommandResponse.data is not interpreted in any way. With xmit_static
+///or xmit_bytes this sends commands hf2 has no function for.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// // a vendor command
+/// hf2::xmit_static::<65>(0x8001, 1, &[], &d).unwrap();
+/// let response = hf2::rx(&d).unwrap();
+/// assert_eq!(response.status, hf2::CommandResponseStatus::Success);
+/// # }
+/// ```
+pub fn rx(d: &impl ReadWrite) -> Result<CommandResponse, Error> {
+    receive(|buffer| d.hf2_read(buffer))
+}
+
+///rx waiting up to timeout for the whole response instead of the transports default for each
+///report. Every read gets what is left of timeout, so empty reads retried by receive dont each
+///wait the full timeout again, and running out is an Error::Timeout.
+pub(crate) fn rx_timeout(d: &impl ReadWrite, timeout: Duration) -> Result<CommandResponse, Error> {
+    rx_timeout_with_clock(d, timeout, &SystemClock)
+}
+
+///rx_timeout timing the reads with clock.
+pub(crate) fn rx_timeout_with_clock(
+    d: &impl ReadWrite,
+    timeout: Duration,
+    clock: &impl Clock,
+) -> Result<CommandResponse, Error> {
+    let mut left = timeout;
+    receive(|buffer| {
+        if left.is_zero() {
+            return Err(Error::Timeout);
+        }
+        let start = clock.now();
+        let read = d.hf2_read_timeout(buffer, left);
+        left = left.saturating_sub(clock.now().duration_since(start));
+        read
+    })
+}
+
+///Most a response can hold, far more than any max_message_size seen.
+const MAX_RESPONSE: usize = 64 * 1024;
+
+///Reads packets until a Final one, growing the message as its packets arrive rather than
+///allocating MAX_RESPONSE up front, as this runs once per page written.
+fn receive(
+    mut read: impl FnMut(&mut [u8]) -> Result<usize, Error>,
+) -> Result<CommandResponse, Error> {
+    let mut message = Vec::new();
 
     let buffer = &mut [0_u8; 64];
     let mut retries = 5;
 
     // keep reading until Final packet
-    'outer: while {
-        let count = d.hf2_read(buffer)?;
+    loop {
+        let count = read(buffer)?;
 
         log::debug!("rx count: {:?}", count);
 
@@ -206,45 +379,321 @@ pub(crate) fn rx(d: &impl ReadWrite) -> Result<CommandResponse, Error> {
                 return Err(Error::Parse);
             } else {
                 retries -= 1;
-                continue 'outer;
+                continue;
+            }
+        }
+
+        // a misbehaving ReadWrite could claim more than the buffer holds
+        let packet = buffer.get(..count).ok_or(Error::Parse)?;
+        let (ptype, payload) = decode_packet(packet)?;
+        match ptype {
+            PacketType::StdOut | PacketType::Stderr => {
+                log::debug!("{:?} while waiting on a response: {:02X?}", ptype, payload)
+            }
+            PacketType::Inner | PacketType::Final => {
+                if message.len() + payload.len() > MAX_RESPONSE {
+                    return Err(Error::Parse);
+                }
+                message.extend_from_slice(payload);
+                if ptype == PacketType::Final {
+                    break;
+                }
             }
         }
+    }
 
-        let ptype = PacketType::try_from(buffer[0] >> 6)?;
+    if message.len() < 4 {
+        log::debug!("rx response too short: {:02X?}", message);
+        return Err(Error::ResponseTooShort {
+            received: message.len(),
+        });
+    }
 
-        log::debug!("rx ptype: {:?}", ptype);
+    let resp = message.as_slice().pread_with(0, LE)?;
 
-        let len: usize = (buffer[0] & 0x3F) as usize;
+    log::debug!("{:?}", resp);
 
-        log::debug!("rx len: {:?}", len);
+    Ok(resp)
+}
 
-        if len >= count {
-            return Err(Error::Parse);
+///Most payload one packet carries, a 64 byte report less its header byte.
+pub const MAX_PACKET_PAYLOAD: usize = 63;
+
+///A packets header byte, its type in the top 2 bits and payload length in the low 6.
+fn packet_header(ptype: PacketType, len: usize) -> u8 {
+    (ptype as u8) << 6 | (len as u8 & 0x3F)
+}
+
+///Splits total bytes of message into packets of up to packet_size payload bytes, each of type
+///ptype except the last which is last. Each packet is built on the stack and handed to out.
+fn frame<'a>(
+    mut message: impl Iterator<Item = &'a u8>,
+    total: usize,
+    packet_size: usize,
+    ptype: PacketType,
+    last: PacketType,
+    mut out: impl FnMut(&[u8]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let packet_size = core::cmp::min(packet_size, MAX_PACKET_PAYLOAD);
+    if packet_size == 0 {
+        return Err(Error::Arguments);
+    }
+
+    let mut buffer = [0_u8; MAX_PACKET_PAYLOAD + 1];
+    let mut sent = 0;
+
+    while sent < total {
+        let len = core::cmp::min(packet_size, total - sent);
+        let packet = buffer.get_mut(..=len).ok_or(Error::Arguments)?;
+        let (header, payload) = packet.split_first_mut().ok_or(Error::Arguments)?;
+        for (slot, byte) in payload.iter_mut().zip(&mut message) {
+            *slot = *byte;
         }
+        sent += len;
 
-        log::debug!(
-            "rx header: {:02X?} data: {:02X?}",
-            &buffer[0],
-            &buffer[1..(len + 1)]
-        );
+        *header = packet_header(if sent == total { last } else { ptype }, len);
+        out(packet)?;
+    }
+    Ok(())
+}
 
-        //skip the header byte and strip excess bytes remote is allowed to send
-        bitsnbytes.extend_from_slice(&buffer[1..(len + 1)]);
+///Frames a command into HF2 packets, the header byte then up to packet_size payload bytes, at most
+///63, and calls out with each in order. The payloads joined are the command, id, tag, two zero
+///reserved bytes and data, which should already be LE. All but the last packet are Inner and
+///full, the last is Final. The 8 byte command header has to fit in the first packet so a
+///packet_size under 8 is an Error::Arguments, and an error from out stops framing and is returned.
+///Allocation free, and with defragment the framing everything else in hf2 is built on, so a
+///reference to test device side implementations against. Reports on the wire are the report ID,
+///usually 0, followed by a packet.
+///
+/// # Examples
+///
+/// ```
+/// let mut packets = vec![];
+/// hf2::frame_command(0x0006, 7, &[0xAA; 56], 63, |packet| {
+///     packets.push(packet.to_vec());
+///     Ok(())
+/// })
+/// .unwrap();
+///
+/// // the first packet has the 8 byte header and 55 data bytes, the rest spill into a second
+/// assert_eq!(packets.len(), 2);
+/// assert_eq!(packets[0][..9], [0x3F, 0x06, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00]);
+/// assert_eq!(packets[0].len(), 64);
+/// assert_eq!(packets[1], [0x41, 0xAA]);
+/// ```
+pub fn frame_command(
+    id: u32,
+    tag: u16,
+    data: &[u8],
+    packet_size: usize,
+    out: impl FnMut(&[u8]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    if packet_size < 8 {
+        return Err(Error::Arguments);
+    }
 
-        //funky do while notation
-        ptype == PacketType::Inner
-    } {}
+    //command struct is 8 bytes, the two reserved bytes stay zero
+    let mut header = [0_u8; 8];
+    header.pwrite_with(id, 0, LE)?;
+    header.pwrite_with(tag, 4, LE)?;
+
+    frame(
+        header.iter().chain(data),
+        header.len() + data.len(),
+        packet_size,
+        PacketType::Inner,
+        PacketType::Final,
+        out,
+    )
+}
 
-    let resp = bitsnbytes.as_slice().pread_with(0, LE)?;
+///Frames a message as it is, like frame_command. For a command already serialized, or on the
+///device side a response of tag, status, status info and data.
+///
+/// # Examples
+///
+/// ```
+/// let mut packets = vec![];
+/// // tag 7, success, no data
+/// hf2::frame_message(&[0x07, 0x00, 0x00, 0x00], 63, |packet| {
+///     packets.push(packet.to_vec());
+///     Ok(())
+/// })
+/// .unwrap();
+///
+/// assert_eq!(packets, vec![vec![0x44, 0x07, 0x00, 0x00, 0x00]]);
+/// ```
+pub fn frame_message(
+    message: &[u8],
+    packet_size: usize,
+    out: impl FnMut(&[u8]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    frame(
+        message.iter(),
+        message.len(),
+        packet_size,
+        PacketType::Inner,
+        PacketType::Final,
+        out,
+    )
+}
 
-    log::debug!("{:?}", resp);
+///Frames serial output as packets of ptype, StdOut or Stderr, up to 63 bytes each. Unlike messages
+///each packet stands alone. Any other ptype is an Error::Arguments.
+///
+/// # Examples
+///
+/// ```
+/// use hf2::PacketType;
+///
+/// let mut packets = vec![];
+/// hf2::frame_serial(b"hi", PacketType::Stderr, |packet| {
+///     packets.push(packet.to_vec());
+///     Ok(())
+/// })
+/// .unwrap();
+///
+/// assert_eq!(packets, vec![vec![0xC2, b'h', b'i']]);
+/// ```
+pub fn frame_serial(
+    data: &[u8],
+    ptype: PacketType,
+    out: impl FnMut(&[u8]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    match ptype {
+        PacketType::StdOut | PacketType::Stderr => frame(
+            data.iter(),
+            data.len(),
+            MAX_PACKET_PAYLOAD,
+            ptype,
+            ptype,
+            out,
+        ),
+        _ => Err(Error::Arguments),
+    }
+}
 
-    Ok(resp)
+///Reassembly state for defragment, the message so far is kept in buffer which bounds how big a
+///message can get. Any AsMut<[u8]>, ex an array where there is no heap.
+#[derive(Debug)]
+pub struct Defrag<B> {
+    buffer: B,
+    len: usize,
+    complete: bool,
+}
+
+impl<B: AsMut<[u8]>> Defrag<B> {
+    pub fn new(buffer: B) -> Self {
+        Defrag {
+            buffer,
+            len: 0,
+            complete: false,
+        }
+    }
+}
+
+///What a packet given to defragment completed.
+#[derive(Debug, PartialEq)]
+pub enum Complete<'a> {
+    ///A command or response, the payloads of its packets joined.
+    Message(&'a [u8]),
+    ///One packet of serial output, which isnt part of any message.
+    Serial(PacketType, &'a [u8]),
+}
+
+///Feeds a packet, header byte first without any report ID, to state. Returns the message when its
+///Final packet arrives, serial output right away, and None while a message is still coming in.
+///The next packet after a message starts a new one. Bytes past the length in the header are
+///ignored as devices may pad packets. An empty packet, one shorter than its header says or a
+///message outgrowing states buffer is an Error::Parse. Allocation free, the dual of frame_command.
+///
+/// # Examples
+///
+/// ```
+/// use hf2::{defragment, Complete, Defrag};
+///
+/// let mut state = Defrag::new([0_u8; 256]);
+///
+/// assert_eq!(defragment(&[0x02, 0x07, 0x00], &mut state).unwrap(), None);
+/// assert_eq!(
+///     defragment(&[0x82, b'h', b'i'], &mut state).unwrap(),
+///     Some(Complete::Serial(hf2::PacketType::StdOut, b"hi"))
+/// );
+/// assert_eq!(
+///     defragment(&[0x42, 0x00, 0x00, 0xFF, 0xFF], &mut state).unwrap(),
+///     Some(Complete::Message(&[0x07, 0x00, 0x00, 0x00]))
+/// );
+/// ```
+pub fn defragment<'a, B: AsMut<[u8]>>(
+    packet: &'a [u8],
+    state: &'a mut Defrag<B>,
+) -> Result<Option<Complete<'a>>, Error> {
+    let (ptype, payload) = decode_packet(packet)?;
+
+    if let PacketType::StdOut | PacketType::Stderr = ptype {
+        return Ok(Some(Complete::Serial(ptype, payload)));
+    }
+
+    if state.complete {
+        state.len = 0;
+        state.complete = false;
+    }
+    let end = state.len + payload.len();
+    state
+        .buffer
+        .as_mut()
+        .get_mut(state.len..end)
+        .ok_or(Error::Parse)?
+        .copy_from_slice(payload);
+    state.len = end;
+
+    match ptype {
+        PacketType::Final => {
+            state.complete = true;
+            let message = state.buffer.as_mut().get(..end).ok_or(Error::Parse)?;
+            Ok(Some(Complete::Message(message)))
+        }
+        _ => Ok(None),
+    }
+}
+
+///Splits a received packet into its type and payload.
+fn decode_packet(packet: &[u8]) -> Result<(PacketType, &[u8]), Error> {
+    let (header, payload) = packet.split_first().ok_or(Error::Parse)?;
+
+    let ptype = PacketType::try_from(header >> 6)?;
+
+    log::debug!("rx ptype: {:?}", ptype);
+
+    let len: usize = (header & 0x3F) as usize;
+
+    log::debug!("rx len: {:?}", len);
+
+    //strip excess bytes remote is allowed to send
+    let payload = payload.get(..len).ok_or(Error::Parse)?;
+
+    log::debug!("rx header: {:02X?} data: {:02X?}", header, payload);
+
+    Ok((ptype, payload))
+}
+
+///Reads a single packet as is, for callers doing their own dispatch of command responses and
+///serial output. A read of nothing is an Error::Parse.
+pub fn read_packet(d: &impl ReadWrite) -> Result<(PacketType, Vec<u8>), Error> {
+    let buffer = &mut [0_u8; 64];
+    let count = d.hf2_read(buffer)?;
+
+    let (ptype, payload) = decode_packet(buffer.get(..count).ok_or(Error::Parse)?)?;
+    Ok((ptype, payload.to_vec()))
 }
 
 #[cfg(test)]
-mod tests {
+#[allow(clippy::indexing_slicing)]
+pub(crate) mod tests {
     use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
 
     #[allow(dead_code)]
     pub struct MyMock<R, W>
@@ -277,6 +726,459 @@ mod tests {
         }
     }
 
+    ///Records every report written to it and hands out queued reports on read
+    #[derive(Default)]
+    pub(crate) struct CapturingMock {
+        responses: RefCell<VecDeque<Vec<u8>>>,
+        captured: RefCell<Vec<Vec<u8>>>,
+        timeouts: RefCell<Vec<Duration>>,
+    }
+
+    impl CapturingMock {
+        pub(crate) fn new() -> Self {
+            Self::default()
+        }
+
+        ///reports returned by hf2_read in order, without report ID
+        pub(crate) fn with_responses(responses: Vec<Vec<u8>>) -> Self {
+            Self {
+                responses: RefCell::new(responses.into()),
+                ..Self::default()
+            }
+        }
+
+        ///every report written so far, including the report ID
+        pub(crate) fn captured(&self) -> Vec<Vec<u8>> {
+            self.captured.borrow().clone()
+        }
+
+        ///timeout of every hf2_read_timeout call so far
+        pub(crate) fn timeouts(&self) -> Vec<Duration> {
+            self.timeouts.borrow().clone()
+        }
+    }
+
+    impl ReadWrite for CapturingMock {
+        fn hf2_write(&self, data: &[u8]) -> Result<usize, Error> {
+            self.captured.borrow_mut().push(data.to_vec());
+            Ok(data.len())
+        }
+        fn hf2_read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+            match self.responses.borrow_mut().pop_front() {
+                //reports longer than buf are cut off but their full length is still returned,
+                //like a misbehaving ReadWrite implementation would
+                Some(report) => {
+                    let len = report.len().min(buf.len());
+                    buf[..len].copy_from_slice(&report[..len]);
+                    Ok(report.len())
+                }
+                None => Ok(0),
+            }
+        }
+        fn hf2_read_timeout(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+            self.timeouts.borrow_mut().push(timeout);
+            self.hf2_read(buf)
+        }
+    }
+
+    #[cfg(feature = "utils")]
+    pub(crate) use crate::simulator::simulated_flash;
+    pub(crate) use crate::simulator::DeviceSimulator;
+
+    ///Builds the reports, report ID included, that xmit should send for a command.
+    ///Deliberately written without frame_command so tests compare against an independent framing.
+    pub(crate) fn build_command_packets(id: u32, tag: u16, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut message = Vec::with_capacity(data.len() + 8);
+        message.extend_from_slice(&id.to_le_bytes());
+        message.extend_from_slice(&tag.to_le_bytes());
+        message.extend_from_slice(&[0x00, 0x00]);
+        message.extend_from_slice(data);
+
+        //the 8 byte command header plus data is split into 63 byte packet payloads
+        let chunks: Vec<&[u8]> = message.chunks(63).collect();
+        chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let ptype = if i == chunks.len() - 1 {
+                    PacketType::Final
+                } else {
+                    PacketType::Inner
+                };
+                let mut packet = vec![0x00, (ptype as u8) << 6 | chunk.len() as u8];
+                packet.extend_from_slice(chunk);
+                packet
+            })
+            .collect()
+    }
+
+    #[test]
+    fn build_command_packets_no_data() {
+        assert_eq!(
+            build_command_packets(0x0001, 0, &[]),
+            vec![vec![
+                0x00, 0x48, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+            ]]
+        );
+    }
+
+    #[test]
+    fn frame_defragment_round_trip() {
+        for packet_size in 8..=MAX_PACKET_PAYLOAD + 1 {
+            for len in 0..300_usize {
+                let data: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+                let mut state = Defrag::new([0_u8; 512]);
+                let mut packets = 0;
+                let mut message = None;
+
+                frame_command(0x0006, 7, &data, packet_size, |packet| {
+                    assert!(message.is_none(), "packet after the Final one");
+                    assert!(packet.len() <= MAX_PACKET_PAYLOAD + 1);
+                    packets += 1;
+                    if let Some(Complete::Message(complete)) = defragment(packet, &mut state)? {
+                        message = Some(complete.to_vec());
+                    }
+                    Ok(())
+                })
+                .unwrap();
+
+                let message = message.unwrap();
+                let payload = packet_size.min(MAX_PACKET_PAYLOAD);
+                assert_eq!(packets, (len + 8).div_ceil(payload));
+                assert_eq!(
+                    message[..8],
+                    [0x06, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00]
+                );
+                assert_eq!(message[8..], data[..]);
+            }
+        }
+
+        assert!(matches!(
+            frame_command(0x0006, 7, &[], 7, |_| Ok(())),
+            Err(Error::Arguments)
+        ));
+        assert!(matches!(
+            frame_serial(b"hi", PacketType::Final, |_| Ok(())),
+            Err(Error::Arguments)
+        ));
+    }
+
+    #[test]
+    fn defragment_errors() {
+        let mut state = Defrag::new([0_u8; 64]);
+
+        // empty, shorter than its length, and outgrowing the buffer
+        assert!(matches!(defragment(&[], &mut state), Err(Error::Parse)));
+        assert!(matches!(
+            defragment(&[0x43, 0x00], &mut state),
+            Err(Error::Parse)
+        ));
+        assert!(defragment(&[0x3F; 64], &mut state).unwrap().is_none());
+        assert!(matches!(
+            defragment(&[0x3F; 64], &mut state),
+            Err(Error::Parse)
+        ));
+    }
+
+    #[test]
+    fn build_command_packets_matches_xmit() {
+        let data: Vec<u8> = (0..=255).collect();
+        let mock = CapturingMock::new();
+
+        xmit(Command::new(0x0006, 7, data.clone()), &mock).unwrap();
+
+        assert_eq!(mock.captured(), build_command_packets(0x0006, 7, &data));
+    }
+
+    ///xmits a command with len bytes of data and returns (packet type, payload length) of each report
+    fn xmit_framing(len: usize) -> Vec<(PacketType, usize)> {
+        let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        let mock = CapturingMock::new();
+
+        xmit(Command::new(0x0006, 7, data.clone()), &mock).unwrap();
+
+        let captured = mock.captured();
+        assert_eq!(captured, build_command_packets(0x0006, 7, &data));
+
+        captured
+            .iter()
+            .map(|report| {
+                let (ptype, payload) = decode_packet(&report[1..]).unwrap();
+                assert_eq!(report.len(), payload.len() + 2);
+                (ptype, payload.len())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn xmit_first_packet_full() {
+        assert_eq!(xmit_framing(55), vec![(PacketType::Final, 63)]);
+    }
+
+    #[test]
+    fn xmit_first_packet_overflow() {
+        assert_eq!(
+            xmit_framing(56),
+            vec![(PacketType::Inner, 63), (PacketType::Final, 1)]
+        );
+    }
+
+    #[test]
+    fn xmit_continuation_packet_full() {
+        assert_eq!(
+            xmit_framing(118),
+            vec![(PacketType::Inner, 63), (PacketType::Final, 63)]
+        );
+    }
+
+    #[test]
+    fn xmit_continuation_packet_overflow() {
+        assert_eq!(
+            xmit_framing(119),
+            vec![
+                (PacketType::Inner, 63),
+                (PacketType::Inner, 63),
+                (PacketType::Final, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn xmit_no_trailing_empty_packet() {
+        for (len, packets) in [(118, 2), (181, 3)].iter() {
+            let framing = xmit_framing(*len);
+
+            assert_eq!(framing.len(), *packets);
+            assert_eq!(framing.last(), Some(&(PacketType::Final, 63)));
+            assert!(framing.iter().all(|(_, payload)| *payload > 0));
+        }
+    }
+
+    ///writes one byte short on the nth report
+    struct ShortWriter {
+        short_at: usize,
+        writes: std::cell::Cell<usize>,
+    }
+
+    impl ReadWrite for ShortWriter {
+        fn hf2_write(&self, data: &[u8]) -> Result<usize, Error> {
+            let n = self.writes.get();
+            self.writes.set(n + 1);
+            if n == self.short_at {
+                Ok(data.len() - 1)
+            } else {
+                Ok(data.len())
+            }
+        }
+        fn hf2_read(&self, _buf: &mut [u8]) -> Result<usize, Error> {
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn short_write_is_an_error() {
+        let d = ShortWriter {
+            short_at: 1,
+            writes: std::cell::Cell::new(0),
+        };
+
+        // second of three reports comes up short, and the third is never sent
+        assert!(matches!(
+            xmit(Command::new(0x0006, 0, vec![0; 181]), &d),
+            Err(Error::ShortWrite {
+                expected: 65,
+                wrote: 64
+            })
+        ));
+        assert_eq!(d.writes.get(), 2);
+    }
+
+    #[test]
+    fn xmit_static_smaller_reports() {
+        let data: Vec<u8> = (0..40).collect();
+        let mock = CapturingMock::new();
+
+        xmit_static::<18>(0x0006, 7, &data, &mock).unwrap();
+
+        // 16 byte payloads, so the 48 byte message is 3 reports
+        let mut message = vec![0x06, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00];
+        message.extend_from_slice(&data);
+        let expected: Vec<Vec<u8>> = message
+            .chunks(16)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let ptype = if i == 2 { 0x40 } else { 0x00 };
+                let mut report = vec![0x00, ptype | 16];
+                report.extend_from_slice(chunk);
+                report
+            })
+            .collect();
+        assert_eq!(mock.captured(), expected);
+    }
+
+    #[test]
+    fn xmit_static_buffer_too_small() {
+        let mock = CapturingMock::new();
+
+        assert!(matches!(
+            xmit_static::<9>(0x0001, 0, &[], &mock),
+            Err(Error::Arguments)
+        ));
+        assert!(xmit_static::<10>(0x0001, 0, &[], &mock).is_ok());
+        assert_eq!(mock.captured().len(), 1);
+    }
+
+    #[test]
+    fn xmit_bytes_matches_xmit() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut raw = vec![0x06, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00];
+        raw.extend_from_slice(&data);
+        let mock = CapturingMock::new();
+
+        xmit_bytes(&raw, &mock).unwrap();
+
+        assert_eq!(mock.captured(), build_command_packets(0x0006, 7, &data));
+    }
+
+    #[test]
+    fn xmit_with_report_id_framing() {
+        let data: Vec<u8> = (0..60).collect();
+        let mock = CapturingMock::new();
+
+        xmit_with_report_id(2, 0x0006, 7, &data, &mock).unwrap();
+
+        let expected: Vec<Vec<u8>> = build_command_packets(0x0006, 7, &data)
+            .into_iter()
+            .map(|mut report| {
+                report[0] = 2;
+                report
+            })
+            .collect();
+        assert_eq!(mock.captured(), expected);
+        assert_eq!(mock.captured()[0].len(), 65);
+    }
+
+    #[test]
+    fn xmit_bytes_keeps_reserved_bytes() {
+        let mock = CapturingMock::new();
+
+        xmit_bytes(&[0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0xAA, 0xBB], &mock).unwrap();
+
+        assert_eq!(
+            mock.captured(),
+            vec![vec![
+                0x00, 0x48, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0xAA, 0xBB
+            ]]
+        );
+        assert!(matches!(
+            xmit_bytes(&[0x01, 0x00, 0x00, 0x00], &mock),
+            Err(Error::Arguments)
+        ));
+    }
+
+    #[test]
+    fn read_packet_types() {
+        let mock = CapturingMock::with_responses(vec![
+            vec![0x83, b'h', b'i', b'!', 0xAA],
+            vec![0xC1, b'e'],
+            vec![0x00],
+        ]);
+
+        assert_eq!(
+            read_packet(&mock).unwrap(),
+            (PacketType::StdOut, b"hi!".to_vec())
+        );
+        assert_eq!(
+            read_packet(&mock).unwrap(),
+            (PacketType::Stderr, b"e".to_vec())
+        );
+        assert_eq!(read_packet(&mock).unwrap(), (PacketType::Inner, vec![]));
+    }
+
+    #[test]
+    fn receive_empty_response() {
+        let mock = CapturingMock::with_responses(vec![vec![0x44, 0x07, 0x00, 0x00, 0x00]]);
+
+        let response = CommandResponse {
+            tag: 0x0007,
+            status: CommandResponseStatus::Success,
+            status_info: 0x00,
+            data: vec![],
+        };
+
+        assert_eq!(rx(&mock).unwrap(), response);
+    }
+
+    #[test]
+    fn timeout_is_for_the_whole_response() {
+        use crate::clock::tests::MockClock;
+
+        ///Never answers, each read waits out its timeout.
+        struct Silent<'a>(&'a MockClock);
+
+        impl ReadWrite for Silent<'_> {
+            fn hf2_write(&self, data: &[u8]) -> Result<usize, Error> {
+                Ok(data.len())
+            }
+            fn hf2_read(&self, _: &mut [u8]) -> Result<usize, Error> {
+                Ok(0)
+            }
+            fn hf2_read_timeout(&self, _: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+                self.0.sleep(timeout);
+                Ok(0)
+            }
+        }
+
+        let clock = MockClock::new();
+        let timeout = Duration::from_secs(5);
+
+        assert!(matches!(
+            rx_timeout_with_clock(&Silent(&clock), timeout, &clock),
+            Err(Error::Timeout)
+        ));
+        assert_eq!(clock.elapsed(), timeout);
+
+        // empty reads that return early are retried within the same timeout
+        let mock = CapturingMock::with_responses(vec![vec![], vec![0x44, 0x07, 0x00, 0x00, 0x00]]);
+        assert!(rx_timeout_with_clock(&mock, timeout, &clock).is_ok());
+        assert_eq!(mock.timeouts(), vec![timeout, timeout]);
+    }
+
+    #[test]
+    fn receive_short_response() {
+        let mock = CapturingMock::with_responses(vec![vec![0x02, 0x07, 0x00], vec![0x41, 0x00]]);
+
+        assert!(matches!(
+            rx(&mock),
+            Err(Error::ResponseTooShort { received: 3 })
+        ));
+    }
+
+    #[test]
+    fn receive_malformed_does_not_panic() {
+        let valid = vec![0x48, 0x07, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04];
+
+        let mut corpus: Vec<Vec<Vec<u8>>> = (0..valid.len())
+            .map(|len| vec![valid[..len].to_vec()])
+            .collect();
+        for header in 0..=0xFF {
+            let mut mutated = valid.clone();
+            mutated[0] = header;
+            corpus.push(vec![mutated]);
+        }
+        // more bytes than hf2_read was handed, and an unterminated Inner sequence
+        corpus.push(vec![vec![0x7F; 80]]);
+        corpus.push(vec![vec![0x3F; 64]; 3]);
+
+        for reports in corpus {
+            let result = std::panic::catch_unwind(|| {
+                let mock = CapturingMock::with_responses(reports.clone());
+                let _ = rx(&mock);
+            });
+            assert!(result.is_ok(), "rx panicked on {:02X?}", reports);
+        }
+    }
+
     #[test]
     fn send_fragmented() {
         let data: Vec<Vec<u8>> = vec![
@@ -412,4 +1314,21 @@ mod tests {
         let rsp = rx(&mock).unwrap();
         assert_eq!(rsp, response);
     }
+
+    #[test]
+    fn protocol_error_names_the_command() {
+        let error = ProtocolError::new(0x0006, Error::Transmission);
+        assert_eq!(error.command_name, "WRITE FLASH PAGE");
+        assert_eq!(
+            error.to_string(),
+            "WRITE FLASH PAGE (0x0006): usb transmission failed"
+        );
+
+        let source = std::error::Error::source(&error).unwrap();
+        assert_eq!(source.to_string(), Error::Transmission.to_string());
+        assert_eq!(
+            ProtocolError::new(0x1234, Error::Parse).command_name,
+            "UNKNOWN"
+        );
+    }
 }
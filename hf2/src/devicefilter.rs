@@ -0,0 +1,357 @@
+use crate::{bin_info, info, BinInfoMode, BinInfoResponse, Error, ReadWrite};
+use core::str::FromStr;
+
+///One condition of a DeviceFilter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DevicePredicate {
+    Vid(u16),
+    Pid(u16),
+    ///Serial number starts with this, a device without a serial never matches.
+    SerialPrefix(String),
+    ///BININFO family, ex SAMD51 matches ATSAMD51. Case insensitive. A family hf2 doesnt know has no
+    ///name so never matches.
+    Family(String),
+    ///INFO Board-ID contains this. Case insensitive.
+    BoardIdContains(String),
+    Mode(BinInfoMode),
+}
+
+impl DevicePredicate {
+    ///Whether enumeration alone can answer this, the rest have to ask the device.
+    pub fn is_cheap(&self) -> bool {
+        matches!(
+            self,
+            DevicePredicate::Vid(_) | DevicePredicate::Pid(_) | DevicePredicate::SerialPrefix(_)
+        )
+    }
+
+    ///The key this is written with in a parsed DeviceFilter, ex family.
+    pub fn key(&self) -> &'static str {
+        match self {
+            DevicePredicate::Vid(_) => "vid",
+            DevicePredicate::Pid(_) => "pid",
+            DevicePredicate::SerialPrefix(_) => "serial",
+            DevicePredicate::Family(_) => "family",
+            DevicePredicate::BoardIdContains(_) => "board",
+            DevicePredicate::Mode(_) => "mode",
+        }
+    }
+}
+
+///Conditions a device has to meet, all of them, to be selected. Built with the builder methods or
+///parsed from a comma separated list like `family=SAMD51,mode=bootloader,!serial=ABC123`, where
+///keys are vid, pid, serial, family, board and mode and a leading ! negates.
+///
+/// # Examples
+///
+/// ```
+/// use hf2::{BinInfoMode, DeviceFilter};
+///
+/// let parsed: DeviceFilter = "family=SAMD51,mode=bootloader,!serial=ABC123".parse().unwrap();
+/// let built = DeviceFilter::new()
+///     .family("SAMD51")
+///     .mode(BinInfoMode::Bootloader)
+///     .exclude(hf2::DevicePredicate::SerialPrefix("ABC123".into()));
+/// assert_eq!(parsed, built);
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DeviceFilter {
+    terms: Vec<(bool, DevicePredicate)>,
+}
+
+impl DeviceFilter {
+    ///Matches every device.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn vid(self, vid: u16) -> Self {
+        self.require(DevicePredicate::Vid(vid))
+    }
+
+    pub fn pid(self, pid: u16) -> Self {
+        self.require(DevicePredicate::Pid(pid))
+    }
+
+    pub fn serial_prefix(self, prefix: &str) -> Self {
+        self.require(DevicePredicate::SerialPrefix(prefix.into()))
+    }
+
+    pub fn family(self, family: &str) -> Self {
+        self.require(DevicePredicate::Family(family.into()))
+    }
+
+    pub fn board_id_contains(self, board_id: &str) -> Self {
+        self.require(DevicePredicate::BoardIdContains(board_id.into()))
+    }
+
+    pub fn mode(self, mode: BinInfoMode) -> Self {
+        self.require(DevicePredicate::Mode(mode))
+    }
+
+    pub fn require(mut self, predicate: DevicePredicate) -> Self {
+        self.terms.push((false, predicate));
+        self
+    }
+
+    ///Devices matching predicate are left out.
+    pub fn exclude(mut self, predicate: DevicePredicate) -> Self {
+        self.terms.push((true, predicate));
+        self
+    }
+
+    ///Whether matches_device has anything left to check after matches_ids.
+    pub fn needs_probe(&self) -> bool {
+        self.terms
+            .iter()
+            .any(|(_, predicate)| !predicate.is_cheap())
+    }
+
+    ///Keys of the conditions only matches_device can check, each once, for telling a user which
+    ///of them were left out when devices cant be opened.
+    pub fn probed_keys(&self) -> Vec<&'static str> {
+        let mut keys: Vec<&'static str> = vec![];
+        for (_, predicate) in &self.terms {
+            if !predicate.is_cheap() && !keys.contains(&predicate.key()) {
+                keys.push(predicate.key());
+            }
+        }
+        keys
+    }
+
+    ///Checks the conditions enumeration can answer, the rest are left to matches_device. Run this
+    ///first so only likely devices get opened.
+    pub fn matches_ids(&self, vid: u16, pid: u16, serial: Option<&str>) -> bool {
+        self.terms.iter().all(|(negated, predicate)| {
+            let matched = match predicate {
+                DevicePredicate::Vid(want) => vid == *want,
+                DevicePredicate::Pid(want) => pid == *want,
+                DevicePredicate::SerialPrefix(prefix) => {
+                    matches!(serial, Some(serial) if serial.starts_with(prefix.as_str()))
+                }
+                DevicePredicate::Family(_)
+                | DevicePredicate::BoardIdContains(_)
+                | DevicePredicate::Mode(_) => return true,
+            };
+            matched != *negated
+        })
+    }
+
+    ///Checks the conditions that need the device, sending BININFO and INFO only if a condition
+    ///needs them. A device without BININFO counts as running its app with no family, one without
+    ///INFO as having no Board-ID.
+    pub fn matches_device(&self, d: &impl ReadWrite) -> Result<bool, Error> {
+        let mut bininfo: Option<Option<BinInfoResponse>> = None;
+        let mut board_id: Option<Option<String>> = None;
+
+        for (negated, predicate) in &self.terms {
+            let matched = match predicate {
+                DevicePredicate::Family(family) => {
+                    let bininfo = cached(&mut bininfo, || probe_bininfo(d))?;
+                    bininfo
+                        .as_ref()
+                        .and_then(|bininfo| bininfo.family_id)
+                        .and_then(|family_id| family_id.name())
+                        .is_some_and(|name| name.contains(&family.to_uppercase()))
+                }
+                DevicePredicate::Mode(mode) => {
+                    let bininfo = cached(&mut bininfo, || probe_bininfo(d))?;
+                    match bininfo {
+                        Some(bininfo) => bininfo.mode == *mode,
+                        None => *mode == BinInfoMode::User,
+                    }
+                }
+                DevicePredicate::BoardIdContains(want) => {
+                    let board_id = cached(&mut board_id, || probe_board_id(d))?;
+                    board_id.as_ref().is_some_and(|board_id| {
+                        board_id.to_uppercase().contains(&want.to_uppercase())
+                    })
+                }
+                DevicePredicate::Vid(_)
+                | DevicePredicate::Pid(_)
+                | DevicePredicate::SerialPrefix(_) => continue,
+            };
+            if matched == *negated {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+///Runs probe the first time, later calls get the same answer.
+fn cached<T>(slot: &mut Option<T>, probe: impl FnOnce() -> Result<T, Error>) -> Result<&T, Error> {
+    if slot.is_none() {
+        *slot = Some(probe()?);
+    }
+    slot.as_ref().ok_or(Error::Arguments)
+}
+
+fn probe_bininfo(d: &impl ReadWrite) -> Result<Option<BinInfoResponse>, Error> {
+    match bin_info(d) {
+        Ok(bininfo) => Ok(Some(bininfo)),
+        Err(Error::CommandNotRecognized) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn probe_board_id(d: &impl ReadWrite) -> Result<Option<String>, Error> {
+    match info(d) {
+        Ok(info) => Ok(info.board_id().map(|board_id| board_id.raw)),
+        Err(Error::CommandNotRecognized) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl FromStr for DeviceFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn hex(value: &str) -> Result<u16, String> {
+            u16::from_str_radix(value.trim_start_matches("0x"), 16)
+                .map_err(|_| format!("{} isnt a hex id", value))
+        }
+
+        let mut filter = DeviceFilter::new();
+        for term in s.split(',').map(str::trim).filter(|term| !term.is_empty()) {
+            let (negated, term) = match term.strip_prefix('!') {
+                Some(term) => (true, term),
+                None => (false, term),
+            };
+            let (key, value) = term
+                .split_once('=')
+                .ok_or_else(|| format!("{} should be key=value", term))?;
+            let (key, value) = (key.trim(), value.trim());
+
+            let predicate = match key {
+                "vid" => DevicePredicate::Vid(hex(value)?),
+                "pid" => DevicePredicate::Pid(hex(value)?),
+                "serial" => DevicePredicate::SerialPrefix(value.into()),
+                "family" => DevicePredicate::Family(value.into()),
+                "board" => DevicePredicate::BoardIdContains(value.into()),
+                "mode" => match value {
+                    "bootloader" => DevicePredicate::Mode(BinInfoMode::Bootloader),
+                    "app" | "user" => DevicePredicate::Mode(BinInfoMode::User),
+                    _ => return Err(format!("mode {} isnt bootloader or app", value)),
+                },
+                _ => return Err(format!("unknown key {}", key)),
+            };
+
+            filter = if negated {
+                filter.exclude(predicate)
+            } else {
+                filter.require(predicate)
+            };
+        }
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::tests::DeviceSimulator;
+
+    #[test]
+    fn parse() {
+        let cases: &[(&str, Result<DeviceFilter, ()>)] = &[
+            ("", Ok(DeviceFilter::new())),
+            (
+                "vid=0x239A,pid=003d",
+                Ok(DeviceFilter::new().vid(0x239A).pid(0x003D)),
+            ),
+            (
+                " family=SAMD51 , !serial=ABC ",
+                Ok(DeviceFilter::new()
+                    .family("SAMD51")
+                    .exclude(DevicePredicate::SerialPrefix("ABC".into()))),
+            ),
+            (
+                "mode=app,board=PyGamer",
+                Ok(DeviceFilter::new()
+                    .mode(BinInfoMode::User)
+                    .board_id_contains("PyGamer")),
+            ),
+            ("vid=zz", Err(())),
+            ("mode=sleeping", Err(())),
+            ("colour=red", Err(())),
+            ("serial", Err(())),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                input.parse::<DeviceFilter>().map_err(|_| ()),
+                *expected,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn cheap_terms() {
+        let filter: DeviceFilter = "vid=239a,!serial=ABC,family=SAMD51".parse().unwrap();
+        let cases = [
+            (0x239A, Some("XYZ"), true),
+            (0x239A, None, true),
+            (0x239A, Some("ABC123"), false),
+            (0x1209, Some("XYZ"), false),
+        ];
+
+        for (vid, serial, expected) in &cases {
+            assert_eq!(filter.matches_ids(*vid, 0x003D, *serial), *expected);
+        }
+        assert!(filter.needs_probe());
+        assert_eq!(filter.probed_keys(), vec!["family"]);
+        assert!(!DeviceFilter::new().vid(1).needs_probe());
+        assert!(DeviceFilter::new().vid(1).probed_keys().is_empty());
+    }
+
+    #[test]
+    fn probed_terms() {
+        let device = || {
+            DeviceSimulator::new()
+                .handle(0x0001, |_| {
+                    [1_u32, 512, 1024, 1024, 0x5511_4460]
+                        .iter()
+                        .flat_map(|field| field.to_le_bytes().to_vec())
+                        .collect()
+                })
+                .handle(0x0002, |_| {
+                    b"UF2 Bootloader v3.6.0\r\nBoard-ID: SAMD51J19A-PyGamer-M4\r\n".to_vec()
+                })
+        };
+        let cases = [
+            ("family=samd51,mode=bootloader", true),
+            ("family=SAMD21", false),
+            ("!mode=bootloader", false),
+            ("board=pygamer", true),
+            ("!board=Feather", true),
+        ];
+
+        for (input, expected) in &cases {
+            let filter: DeviceFilter = input.parse().unwrap();
+            assert_eq!(
+                filter.matches_device(&device()).unwrap(),
+                *expected,
+                "{}",
+                input
+            );
+        }
+
+        // without BININFO or INFO a device is an app with no family or board
+        let bare = DeviceSimulator::new();
+        let app: DeviceFilter = "mode=app,!family=SAMD51,!board=PyGamer".parse().unwrap();
+        assert!(app.matches_device(&bare).unwrap());
+
+        // a family hf2 doesnt know has no name to match
+        let unknown = DeviceSimulator::new().handle(0x0001, |_| {
+            [1_u32, 512, 1024, 1024, 0x1234_5678]
+                .iter()
+                .flat_map(|field| field.to_le_bytes().to_vec())
+                .collect()
+        });
+        let family: DeviceFilter = "family=UNKNOWN".parse().unwrap();
+        assert!(!family.matches_device(&unknown).unwrap());
+    }
+}
@@ -1,10 +1,19 @@
 use crate::command::{rx, xmit, Command, CommandResponse, CommandResponseStatus};
-use crate::Error;
+use crate::{Error, ReadWrite};
 use scroll::{ctx, Pread, LE};
 
 ///Return internal log buffer if any. The result is a character array.
-
-pub fn dmesg(d: &hidapi::HidDevice) -> Result<DmesgResponse, Error> {
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// println!("{}", hf2::dmesg(&d).unwrap().logs);
+/// # }
+/// ```
+pub fn dmesg(d: &impl ReadWrite) -> Result<DmesgResponse, Error> {
     xmit(Command::new(0x0010, 0, vec![]), d)?;
 
     match rx(d) {
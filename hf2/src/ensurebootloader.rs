@@ -0,0 +1,195 @@
+use crate::{is_bootloader, reset_into_bootloader, Clock, Error, ReadWrite, SystemClock};
+use std::time::Duration;
+
+/// How often to look for the re-enumerated device.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+///Returns d untouched if it is already in bootloader mode. Otherwise resets it into the bootloader
+///and calls reopen until it hands back a device in bootloader mode, or fails with Error::Timeout.
+///reopen should return None while the device hasnt re-enumerated yet.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// use std::time::Duration;
+///
+/// let mut api = hidapi::HidApi::new().unwrap();
+/// let d = api.open(0x239A, 0x803D).unwrap();
+/// // the bootloader of this board enumerates as 0x003D
+/// let d = hf2::ensure_bootloader_with(
+///     d,
+///     || {
+///         api.refresh_devices().ok()?;
+///         api.open(0x239A, 0x003D).ok()
+///     },
+///     Duration::from_secs(5),
+/// )
+/// .unwrap();
+/// # }
+/// ```
+pub fn ensure_bootloader_with<D: ReadWrite>(
+    d: D,
+    reopen: impl FnMut() -> Option<D>,
+    timeout: Duration,
+) -> Result<D, Error> {
+    ensure_bootloader_with_clock(d, reopen, timeout, &SystemClock)
+}
+
+///ensure_bootloader_with timing the wait with clock.
+pub fn ensure_bootloader_with_clock<D: ReadWrite>(
+    d: D,
+    mut reopen: impl FnMut() -> Option<D>,
+    timeout: Duration,
+    clock: &impl Clock,
+) -> Result<D, Error> {
+    if is_bootloader(&d)? {
+        return Ok(d);
+    }
+
+    reset_into_bootloader(&d)?;
+    // the old handle is gone once the device resets
+    drop(d);
+
+    let start = clock.now();
+    loop {
+        if let Some(d) = reopen() {
+            // a device still on its way down can answer as the app one last time
+            if let Ok(true) = is_bootloader(&d) {
+                return Ok(d);
+            }
+        }
+
+        if clock.now().duration_since(start) >= timeout {
+            return Err(Error::Timeout);
+        }
+        clock.sleep(POLL_INTERVAL);
+    }
+}
+
+///ensure_bootloader_with for hidapi. The bootloader usually enumerates with a different pid than the
+///app, so the device is found again by its serial number and a device without one is an
+///Error::Arguments.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// let mut api = hidapi::HidApi::new().unwrap();
+/// let d = api.open(0x239A, 0x803D).unwrap();
+/// let d = hf2::ensure_bootloader(&mut api, d, Duration::from_secs(5)).unwrap();
+/// ```
+#[cfg(feature = "hidapi")]
+pub fn ensure_bootloader(
+    api: &mut hidapi::HidApi,
+    d: hidapi::HidDevice,
+    timeout: Duration,
+) -> Result<hidapi::HidDevice, Error> {
+    let serial = d
+        .get_serial_number_string()?
+        .filter(|serial| !serial.is_empty())
+        .ok_or(Error::Arguments)?;
+
+    ensure_bootloader_with(
+        d,
+        || {
+            api.refresh_devices().ok()?;
+            api.device_list()
+                .filter(|device_info| device_info.serial_number() == Some(serial.as_str()))
+                .find_map(|device_info| device_info.open_device(api).ok())
+        },
+        timeout,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::tests::MockClock;
+    use crate::command::tests::DeviceSimulator;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn bininfo(mode: u32) -> impl Fn(&[u8]) -> Vec<u8> {
+        move |_| {
+            [mode, 512, 1024, 1024]
+                .iter()
+                .flat_map(|field| field.to_le_bytes().to_vec())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn already_in_bootloader() {
+        let d = DeviceSimulator::new().handle(0x0001, bininfo(1));
+
+        let reopened = ensure_bootloader_with(d, || panic!("shouldnt reopen"), Duration::ZERO);
+
+        assert!(reopened.is_ok());
+    }
+
+    #[test]
+    fn resets_and_reopens() {
+        let reset = Rc::new(Cell::new(false));
+        let d = {
+            let reset = reset.clone();
+            DeviceSimulator::new()
+                .handle(0x0001, bininfo(2))
+                .handle(0x0004, move |_| {
+                    reset.set(true);
+                    vec![]
+                })
+        };
+
+        // gone for a poll, then back as the bootloader
+        let mut polls = 0;
+        let reopen = || {
+            polls += 1;
+            if polls < 2 {
+                None
+            } else {
+                Some(DeviceSimulator::new().handle(0x0001, bininfo(1)))
+            }
+        };
+
+        let reopened = ensure_bootloader_with(d, reopen, Duration::from_secs(5)).unwrap();
+
+        assert!(reset.get());
+        assert!(is_bootloader(&reopened).unwrap());
+    }
+
+    #[test]
+    fn times_out() {
+        let d = DeviceSimulator::new().handle(0x0001, bininfo(2));
+
+        assert!(matches!(
+            ensure_bootloader_with(d, || None, Duration::ZERO),
+            Err(Error::Timeout)
+        ));
+    }
+
+    #[test]
+    fn deadline_with_mock_clock() {
+        let d = DeviceSimulator::new()
+            .handle(0x0001, bininfo(2))
+            .handle(0x0004, |_| vec![]);
+        let clock = MockClock::new();
+        let mut polls = 0;
+
+        let result = ensure_bootloader_with_clock(
+            d,
+            || {
+                polls += 1;
+                None
+            },
+            Duration::from_millis(1000),
+            &clock,
+        );
+
+        // a poll at 0 and every 100ms up to and including the deadline
+        assert!(matches!(result, Err(Error::Timeout)));
+        assert_eq!(polls, 11);
+        assert_eq!(clock.elapsed(), Duration::from_millis(1000));
+    }
+}
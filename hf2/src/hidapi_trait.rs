@@ -1,5 +1,7 @@
 use crate::{Error, ReadWrite};
+use core::convert::TryFrom;
 use hidapi::HidDevice;
+use std::time::Duration;
 
 impl ReadWrite for HidDevice {
     fn hf2_write(&self, data: &[u8]) -> Result<usize, Error> {
@@ -8,6 +10,10 @@ impl ReadWrite for HidDevice {
     fn hf2_read(&self, buf: &mut [u8]) -> Result<usize, Error> {
         self.read_timeout(buf, 1000).map_err(|e| e.into())
     }
+    fn hf2_read_timeout(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let millis = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+        self.read_timeout(buf, millis).map_err(|e| e.into())
+    }
 }
 
 impl From<hidapi::HidError> for Error {
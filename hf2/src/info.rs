@@ -1,9 +1,19 @@
 use crate::command::{rx, xmit, Command, CommandResponse, CommandResponseStatus};
-use crate::Error;
+use crate::{Error, ReadWrite};
 use scroll::{ctx, Pread, LE};
 
 /// Various device information. The result is a character array. See INFO_UF2.TXT in UF2 format for details.
-pub fn info(d: &hidapi::HidDevice) -> Result<InfoResponse, Error> {
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// println!("{}", hf2::info(&d).unwrap().info);
+/// # }
+/// ```
+pub fn info(d: &impl ReadWrite) -> Result<InfoResponse, Error> {
     xmit(Command::new(0x0002, 0, vec![]), d)?;
 
     match rx(d) {
@@ -23,6 +33,108 @@ pub struct InfoResponse {
     pub info: String,
 }
 
+impl InfoResponse {
+    ///Value of a `Name: value` line, ex field("Model") is Some("PyGamer").
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.info.lines().find_map(|line| {
+            let (key, value) = line.split_at(line.find(':')?);
+            if key.trim() == name {
+                Some(value[1..].trim())
+            } else {
+                None
+            }
+        })
+    }
+
+    ///The Board-ID line split into its parts.
+    pub fn board_id(&self) -> Option<BoardId> {
+        self.field("Board-ID").map(BoardId::parse)
+    }
+
+    ///The Model line, ex PyGamer.
+    pub fn model(&self) -> Option<&str> {
+        self.field("Model")
+    }
+
+    ///Version from the first line, ex 3.6.0 from `UF2 Bootloader v3.6.0 SFHWRO`.
+    pub fn bootloader_version(&self) -> Option<&str> {
+        let mut words = self.info.lines().next()?.split_whitespace();
+        words.find(|word| *word == "Bootloader")?;
+        words.next()?.strip_prefix('v')
+    }
+
+    ///bootloader_version parsed, for comparing against versions known to lack a command.
+    pub fn version(&self) -> Option<BootloaderVersion> {
+        self.bootloader_version().and_then(BootloaderVersion::parse)
+    }
+}
+
+///major.minor.patch of the bootloader, ordered so versions can be compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BootloaderVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl BootloaderVersion {
+    ///Parses 3.6.0, trailing build info like the -7-gabc of 3.15.0-7-gabc is ignored and a
+    ///missing patch is 0.
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.split(&['-', '+'][..]).next()?;
+        let mut parts = version.split('.').map(str::parse::<u32>);
+
+        let major = parts.next()?.ok()?;
+        let minor = parts.next()?.ok()?;
+        let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(BootloaderVersion {
+            major,
+            minor,
+            patch,
+        })
+    }
+}
+
+///A Board-ID like SAMD51J19A-PyGamer-M4 is chip, board and variant separated by dashes. Ids that
+///dont follow that only have raw.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoardId {
+    pub raw: String,
+    pub chip: Option<String>,
+    pub board: Option<String>,
+    pub variant: Option<String>,
+}
+
+impl BoardId {
+    pub fn parse(raw: &str) -> Self {
+        let parts: Vec<&str> = raw.split('-').collect();
+
+        match parts.as_slice() {
+            // board names can contain dashes themselves
+            [chip, board @ .., variant]
+                if !board.is_empty() && !chip.is_empty() && !variant.is_empty() =>
+            {
+                BoardId {
+                    raw: raw.into(),
+                    chip: Some((*chip).into()),
+                    board: Some(board.join("-")),
+                    variant: Some((*variant).into()),
+                }
+            }
+            _ => BoardId {
+                raw: raw.into(),
+                chip: None,
+                board: None,
+                variant: None,
+            },
+        }
+    }
+}
+
 impl<'a> ctx::TryFromCtx<'a, scroll::Endian> for InfoResponse {
     type Error = Error;
     fn try_from_ctx(this: &'a [u8], le: scroll::Endian) -> Result<(Self, usize), Self::Error> {
@@ -40,6 +152,7 @@ impl<'a> ctx::TryFromCtx<'a, scroll::Endian> for InfoResponse {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::command::tests::DeviceSimulator;
 
     #[test]
     fn parse_response() {
@@ -60,4 +173,76 @@ info: "UF2 Bootloader v3.6.0 SFHWRO\r\nModel: PyGamer\r\nBoard-ID: SAMD51J19A-Py
 
         assert_eq!(res, info_result);
     }
+
+    #[test]
+    fn board_id() {
+        let info = InfoResponse {
+            info: "UF2 Bootloader v3.6.0 SFHWRO\r\nModel: PyGamer\r\nBoard-ID: SAMD51J19A-PyGamer-M4\r\n"
+                .into(),
+        };
+
+        assert_eq!(info.field("Model"), Some("PyGamer"));
+        assert_eq!(info.model(), Some("PyGamer"));
+        assert_eq!(info.bootloader_version(), Some("3.6.0"));
+        assert_eq!(
+            info.version(),
+            Some(BootloaderVersion {
+                major: 3,
+                minor: 6,
+                patch: 0
+            })
+        );
+        assert_eq!(
+            info.board_id(),
+            Some(BoardId {
+                raw: "SAMD51J19A-PyGamer-M4".into(),
+                chip: Some("SAMD51J19A".into()),
+                board: Some("PyGamer".into()),
+                variant: Some("M4".into()),
+            })
+        );
+    }
+
+    #[test]
+    fn versions() {
+        let v = |major, minor, patch| BootloaderVersion {
+            major,
+            minor,
+            patch,
+        };
+
+        assert_eq!(BootloaderVersion::parse("3.15.0-7-gabc"), Some(v(3, 15, 0)));
+        assert_eq!(BootloaderVersion::parse("2.1"), Some(v(2, 1, 0)));
+        assert!(v(3, 15, 0) > v(3, 6, 0));
+        for bad in &["", "3", "3.x.0", "3.6.0.1"] {
+            assert_eq!(BootloaderVersion::parse(bad), None);
+        }
+    }
+
+    #[test]
+    fn board_id_fallback() {
+        assert_eq!(
+            BoardId::parse("NRF52840-Feather-Express-v1").board,
+            Some("Feather-Express".into())
+        );
+
+        for raw in &["PyGamer", "SAMD51-PyGamer", "-PyGamer-M4", ""] {
+            let board_id = BoardId::parse(raw);
+            assert_eq!(board_id.raw, *raw);
+            assert_eq!(board_id.chip, None);
+            assert_eq!(board_id.board, None);
+            assert_eq!(board_id.variant, None);
+        }
+    }
+
+    #[test]
+    fn multibyte_split_across_packets() {
+        // the first packet carries 59 bytes of data after the response header, so the 2 byte é
+        // straddles the first two packets
+        let text = format!("{}é\r\nModel: Café\r\n", "x".repeat(58));
+        let reply = text.clone();
+        let device = DeviceSimulator::new().handle(0x0002, move |_| reply.as_bytes().to_vec());
+
+        assert_eq!(info(&device).unwrap().info, text);
+    }
 }
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 /// This command states the current mode of the device:
 mod bininfo;
 pub use bininfo::*;
@@ -10,14 +12,34 @@ pub use checksumpages::*;
 mod dmesg;
 pub use dmesg::*;
 
+///Pick devices by ids, family, board or mode.
+mod devicefilter;
+pub use devicefilter::*;
+
+///Get a device into bootloader mode, resetting and reconnecting to it if needed.
+mod ensurebootloader;
+pub use ensurebootloader::*;
+
+///Time source for code waiting on a device.
+mod clock;
+pub use clock::{Clock, SystemClock};
+
 /// Various device information. The result is a character array. See INFO_UF2.TXT in UF2 format for details.
 mod info;
 pub use info::*;
 
+///Opt in logging of just hf2 from the HF2_LOG environment variable.
+mod logging;
+pub use logging::*;
+
 ///Read a number of words from memory. Memory is read word by word (and not byte by byte), and target_addr must be suitably aligned. This is to support reading of special IO regions.
 mod readwords;
 pub use readwords::*;
 
+///Retry a command on a freshly opened handle after a transmission error.
+mod reopen;
+pub use reopen::*;
+
 ///Reset the device into user-space app. Usually, no response at all will arrive for this command.
 mod resetintoapp;
 pub use resetintoapp::*;
@@ -26,10 +48,26 @@ pub use resetintoapp::*;
 mod resetintobootloader;
 pub use resetintobootloader::*;
 
+///Serialize commands from several threads on one device.
+mod shared;
+pub use shared::*;
+
+///io::Write to the devices console over StdOut packets.
+mod stdoutwriter;
+pub use stdoutwriter::*;
+
 /// When issued in bootloader mode, it has no effect. In user-space mode it causes handover to bootloader. A BININFO command can be issued to verify that.
 mod startflash;
 pub use startflash::*;
 
+///Keeps devices with a bootloader watchdog alive across gaps in traffic.
+mod watchdog;
+pub use watchdog::*;
+
+///Little endian conversions between words and bytes, for commands taking or returning words.
+mod words;
+pub use words::*;
+
 ///Write a single page of flash memory. No Result.
 mod writeflashpage;
 pub use writeflashpage::*;
@@ -40,8 +78,17 @@ pub use writewords::*;
 
 /// Errors and traits to build a command
 mod command;
+pub use command::{
+    defragment, frame_command, frame_message, frame_serial, read_packet, rx, xmit_bytes,
+    xmit_static, xmit_with_report_id, CommandResponse, CommandResponseStatus, Complete, Defrag,
+    PacketType, ProtocolError, MAX_PACKET_PAYLOAD,
+};
 
+/// Errors returned by the hf2 commands.
+///
+/// New variants may be added in minor releases, so matches on this type need a wildcard arm.
 #[derive(Clone, Debug)]
+#[non_exhaustive]
 pub enum Error {
     Arguments,
     Parse,
@@ -49,16 +96,57 @@ pub enum Error {
     Execution,
     Sequence,
     Transmission,
+    ///The reassembled response didnt hold the 4 byte response header, received is how many bytes it had.
+    ResponseTooShort {
+        received: usize,
+    },
+    ///Gave up waiting on the device.
+    Timeout,
+    ///The transport wrote fewer bytes of a report than it was given.
+    ShortWrite {
+        expected: usize,
+        wrote: usize,
+    },
 }
 
 ///trait to implement HID devices
 pub trait ReadWrite {
     fn hf2_write(&self, data: &[u8]) -> Result<usize, Error>;
     fn hf2_read(&self, buf: &mut [u8]) -> Result<usize, Error>;
+    ///hf2_read waiting up to timeout for a report. Transports without a configurable timeout can leave the default which ignores it.
+    fn hf2_read_timeout(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let _ = timeout;
+        self.hf2_read(buf)
+    }
+}
+
+///HID usage page of the vendor defined interface HF2 runs over.
+pub const HF2_USAGE_PAGE: u16 = 0xFF97;
+
+///HID usage of the HF2 interface within HF2_USAGE_PAGE.
+pub const HF2_USAGE: u16 = 0x0001;
+
+///Whether an enumerated HID interface looks like HF2. Backends that cant report usages, ex hidapi
+///on libusb, give 0 for both so those are let through rather than hiding every device.
+pub fn is_hf2_usage(usage_page: u16, usage: u16) -> bool {
+    (usage_page == HF2_USAGE_PAGE && usage == HF2_USAGE) || (usage_page == 0 && usage == 0)
 }
 
+///Response timeout for START_FLASH and the first page write. Either can wait on a flash erase
+///which on some parts takes seconds, far longer than a normal response, so they get their own
+///timeout rather than stretching it for every command. It bounds the whole response, not each
+///read, so a device that stopped answering fails after ERASE_TIMEOUT.
+pub const ERASE_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[cfg(feature = "hidapi")]
 mod hidapi_trait;
 
+#[cfg(test)]
+mod simulator;
+
 #[cfg(feature = "utils")]
 pub mod utils;
+
+///Command line flags shared by hf2-cli and cargo-hf2.
+#[cfg(feature = "cli")]
+pub mod cli;
@@ -0,0 +1,43 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use std::sync::OnceLock;
+
+static INIT: OnceLock<()> = OnceLock::new();
+static LOGGER: Hf2Logger = Hf2Logger;
+
+///Prints hf2's own records to stderr, everything else is dropped.
+struct Hf2Logger;
+
+impl Log for Hf2Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target().starts_with("hf2") && metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+///Logs hf2 and nothing else to stderr at the level in HF2_LOG, one of trace, debug, info, warn or
+///error, to debug a device without turning on every crates logs. Does nothing when HF2_LOG isnt
+///set or a logger is already installed, which always takes precedence. Only the first call does
+///anything. hf2 never calls this itself, a library installing a global logger would get in the way
+///of the program using it, so call it early in main.
+pub fn init_logging() {
+    INIT.get_or_init(|| {
+        let level = match std::env::var("HF2_LOG")
+            .ok()
+            .and_then(|level| level.parse::<LevelFilter>().ok())
+        {
+            Some(level) => level,
+            None => return,
+        };
+
+        if log::set_logger(&LOGGER).is_ok() {
+            log::set_max_level(level);
+        }
+    });
+}
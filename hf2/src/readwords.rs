@@ -1,18 +1,29 @@
 use crate::command::{rx, xmit, Command, CommandResponse, CommandResponseStatus};
-use crate::Error;
+use crate::{le_bytes_to_words, Error, ReadWrite};
+use core::convert::TryFrom;
 use scroll::{ctx, Pread, Pwrite, LE};
 
 ///Read a number of words from memory. Memory is read word by word (and not byte by byte), and target_addr must be suitably aligned. This is to support reading of special IO regions.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// let words = hf2::read_words(&d, 0x2000_0000, hf2::WordCount(4)).unwrap().words;
+/// # }
+/// ```
 pub fn read_words(
-    d: &hidapi::HidDevice,
+    d: &impl ReadWrite,
     target_address: u32,
-    num_words: u32,
+    num_words: WordCount,
 ) -> Result<ReadWordsResponse, Error> {
     let mut buffer = vec![0_u8; 8];
     let mut offset = 0;
 
     buffer.gwrite_with(target_address, &mut offset, scroll::LE)?;
-    buffer.gwrite_with(num_words, &mut offset, scroll::LE)?;
+    buffer.gwrite_with(num_words.0, &mut offset, scroll::LE)?;
 
     xmit(Command::new(0x0008, 0, buffer), d)?;
 
@@ -27,6 +38,30 @@ pub fn read_words(
     }
 }
 
+///A length in bytes. Memory is addressed in bytes but READ_WORDS and WRITE_WORDS count words, the
+///two types keep one from being passed as the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteLen(pub usize);
+
+///A number of 32 bit words, see ByteLen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WordCount(pub u32);
+
+impl WordCount {
+    ///Words needed to hold len bytes, a partial trailing word counts whole. None past u32::MAX words.
+    pub fn covering(len: ByteLen) -> Option<WordCount> {
+        let words = len.0 / 4 + usize::from(len.0 & 0x3 != 0);
+        u32::try_from(words).ok().map(WordCount)
+    }
+}
+
+impl From<WordCount> for ByteLen {
+    ///Four bytes a word.
+    fn from(words: WordCount) -> ByteLen {
+        ByteLen(words.0 as usize * 4)
+    }
+}
+
 ///Response to the read_words command
 #[derive(Debug, PartialEq)]
 pub struct ReadWordsResponse {
@@ -35,16 +70,45 @@ pub struct ReadWordsResponse {
 
 impl<'a> ctx::TryFromCtx<'a, scroll::Endian> for ReadWordsResponse {
     type Error = Error;
-    fn try_from_ctx(this: &'a [u8], le: scroll::Endian) -> Result<(Self, usize), Self::Error> {
+    fn try_from_ctx(this: &'a [u8], _le: scroll::Endian) -> Result<(Self, usize), Self::Error> {
         if this.len() < 4 {
             return Err(Error::Parse);
         }
 
-        let mut words: Vec<u32> = vec![0; this.len() / 4];
+        let words = le_bytes_to_words(this)?;
 
-        let mut offset = 0;
-        this.gread_inout_with(&mut offset, &mut words, le)?;
+        Ok((ReadWordsResponse { words }, this.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::tests::CapturingMock;
+
+    #[test]
+    fn request_and_response_layout() {
+        let mock = CapturingMock::with_responses(vec![vec![
+            0x4C, 0x00, 0x00, 0x00, 0x00, 0xEF, 0xBE, 0xAD, 0xDE, 0x01, 0x00, 0x00, 0x00,
+        ]]);
+
+        let words = read_words(&mock, 0x2000_0000, WordCount(2)).unwrap();
+
+        assert_eq!(
+            mock.captured(),
+            vec![vec![
+                0x00, 0x50, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20,
+                0x02, 0x00, 0x00, 0x00,
+            ]]
+        );
+        assert_eq!(words.words, vec![0xDEAD_BEEF, 0x0000_0001]);
+    }
 
-        Ok((ReadWordsResponse { words }, offset))
+    #[test]
+    fn byte_and_word_counts() {
+        assert_eq!(WordCount::covering(ByteLen(0)), Some(WordCount(0)));
+        assert_eq!(WordCount::covering(ByteLen(8)), Some(WordCount(2)));
+        assert_eq!(WordCount::covering(ByteLen(9)), Some(WordCount(3)));
+        assert_eq!(ByteLen::from(WordCount(3)), ByteLen(12));
     }
 }
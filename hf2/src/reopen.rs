@@ -0,0 +1,93 @@
+use crate::{Error, ReadWrite};
+
+///Runs op on d. A handle hit by a usb glitch can fail every transfer until reopened, so when op
+///fails with Error::Transmission the device is reopened with reopen, which replaces d, and op is
+///run once more. If reopen returns None the original error is returned.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// let api = hidapi::HidApi::new().unwrap();
+/// let mut d = api.open(0x239A, 0x003D).unwrap();
+/// let reopen = || api.open(0x239A, 0x003D).ok();
+///
+/// let bininfo = hf2::retry_with_reopen(&mut d, reopen, hf2::bin_info).unwrap();
+/// # }
+/// ```
+pub fn retry_with_reopen<D: ReadWrite, T>(
+    d: &mut D,
+    mut reopen: impl FnMut() -> Option<D>,
+    op: impl Fn(&D) -> Result<T, Error>,
+) -> Result<T, Error> {
+    match op(d) {
+        Err(Error::Transmission) => {
+            log::debug!("transmission failed, reopening");
+            *d = reopen().ok_or(Error::Transmission)?;
+            op(d)
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bin_info;
+    use crate::command::tests::DeviceSimulator;
+
+    ///A handle that has either gone bad or works.
+    struct Handle(Option<DeviceSimulator>);
+
+    impl ReadWrite for Handle {
+        fn hf2_write(&self, data: &[u8]) -> Result<usize, Error> {
+            self.0.as_ref().ok_or(Error::Transmission)?.hf2_write(data)
+        }
+        fn hf2_read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+            self.0.as_ref().ok_or(Error::Transmission)?.hf2_read(buf)
+        }
+    }
+
+    fn working() -> Handle {
+        Handle(Some(DeviceSimulator::new().handle(0x0001, |_| {
+            [1_u32, 512, 1024, 1024]
+                .iter()
+                .flat_map(|field| field.to_le_bytes().to_vec())
+                .collect()
+        })))
+    }
+
+    #[test]
+    fn recovers_after_reopen() {
+        let mut d = Handle(None);
+        let mut reopened = 0;
+
+        let bininfo = retry_with_reopen(
+            &mut d,
+            || {
+                reopened += 1;
+                Some(working())
+            },
+            bin_info,
+        )
+        .unwrap();
+
+        assert_eq!(reopened, 1);
+        assert_eq!(bininfo.flash_page_size, 512);
+        assert!(d.0.is_some());
+    }
+
+    #[test]
+    fn gives_up_when_still_failing() {
+        let mut d = Handle(None);
+
+        assert!(matches!(
+            retry_with_reopen(&mut d, || Some(Handle(None)), bin_info),
+            Err(Error::Transmission)
+        ));
+        assert!(matches!(
+            retry_with_reopen(&mut d, || None, bin_info),
+            Err(Error::Transmission)
+        ));
+    }
+}
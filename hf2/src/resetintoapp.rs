@@ -1,7 +1,17 @@
 use crate::command::{xmit, Command};
-use crate::Error;
+use crate::{Error, ReadWrite};
 
 ///Reset the device into user-space app. Empty tuple response.
-pub fn reset_into_app(d: &hidapi::HidDevice) -> Result<(), Error> {
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// hf2::reset_into_app(&d).unwrap();
+/// # }
+/// ```
+pub fn reset_into_app(d: &impl ReadWrite) -> Result<(), Error> {
     xmit(Command::new(0x0003, 0, vec![]), d)
 }
@@ -0,0 +1,132 @@
+use crate::ReadWrite;
+use std::sync::Mutex;
+
+///A device several threads can send commands to. Commands are a write followed by reads of the
+///response, and nothing stops two threads interleaving those on the same device, cross wiring the
+///responses. Tag checks catch some of that but not all, so every command on a SharedDevice runs
+///inside transaction which holds the device to itself until the closure returns.
+///
+///Without a SharedDevice the concurrency model is the ReadWrite types, usually a handle owned by one
+///thread, ex hidapi::HidDevice is Send but not Sync.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// use std::sync::Arc;
+///
+/// let api = hidapi::HidApi::new().unwrap();
+/// let d = Arc::new(hf2::SharedDevice::new(api.open(0x239A, 0x003D).unwrap()));
+///
+/// let logs = {
+///     let d = d.clone();
+///     std::thread::spawn(move || d.transaction(hf2::dmesg))
+/// };
+/// let bininfo = d.transaction(hf2::bin_info).unwrap();
+/// let logs = logs.join().unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct SharedDevice<T: ReadWrite> {
+    inner: Mutex<T>,
+}
+
+impl<T: ReadWrite> SharedDevice<T> {
+    pub fn new(inner: T) -> Self {
+        SharedDevice {
+            inner: Mutex::new(inner),
+        }
+    }
+
+    ///Runs f, usually one or more commands, with no other thread using the device meanwhile. A
+    ///thread that panicked in a transaction may have left a response unread, the device is still
+    ///handed out as there is no better one.
+    pub fn transaction<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        let inner = self
+            .inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        f(&inner)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{defragment, frame_message, read_words, Complete, Defrag, Error, WordCount};
+    use scroll::{Pread, LE};
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+
+    ///Answers READ_WORDS with the address repeated, and yields between each report so unserialized
+    ///callers would interleave.
+    struct AddressEcho {
+        incoming: Mutex<Defrag<Vec<u8>>>,
+        outgoing: Mutex<VecDeque<Vec<u8>>>,
+    }
+
+    impl ReadWrite for AddressEcho {
+        fn hf2_write(&self, data: &[u8]) -> Result<usize, Error> {
+            std::thread::yield_now();
+            let mut incoming = self.incoming.lock().unwrap();
+            if let Some(Complete::Message(command)) = defragment(&data[1..], &mut incoming)? {
+                let address = command.pread_with::<u32>(8, LE)?;
+                let num_words = command.pread_with::<u32>(12, LE)?;
+
+                let mut response = command[4..6].to_vec();
+                response.extend_from_slice(&[0x00, 0x00]);
+                for _ in 0..num_words {
+                    response.extend_from_slice(&address.to_le_bytes());
+                }
+                frame_message(&response, 63, |packet| {
+                    self.outgoing.lock().unwrap().push_back(packet.to_vec());
+                    Ok(())
+                })?;
+            }
+            Ok(data.len())
+        }
+
+        fn hf2_read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+            std::thread::yield_now();
+            match self.outgoing.lock().unwrap().pop_front() {
+                Some(packet) => {
+                    buf[..packet.len()].copy_from_slice(&packet);
+                    Ok(packet.len())
+                }
+                None => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn threads_get_their_own_responses() {
+        let d = Arc::new(SharedDevice::new(AddressEcho {
+            incoming: Mutex::new(Defrag::new(vec![0; 1024])),
+            outgoing: Mutex::new(VecDeque::new()),
+        }));
+
+        let threads: Vec<_> = [0x1000_u32, 0x2000, 0x3000]
+            .iter()
+            .map(|&address| {
+                let d = d.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        let response =
+                            d.transaction(|d| read_words(d, address, WordCount(20)).unwrap());
+                        assert_eq!(response.words, vec![address; 20]);
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! A simulated HF2 bootloader for tests. Only uses crate root paths so the integration tests can
+//! include it with #[path] after importing the same names from hf2.
+#![allow(clippy::indexing_slicing)]
+
+use crate::{
+    defragment, frame_message, CommandResponseStatus, Complete, Defrag, Error, ReadWrite,
+    MAX_PACKET_PAYLOAD,
+};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+#[cfg(feature = "utils")]
+use std::rc::Rc;
+
+type Handler = Box<dyn Fn(&[u8]) -> Vec<u8>>;
+
+///Pretends to be an HF2 bootloader. Commands written to it are reassembled and dispatched by id
+///to the registered handlers whose return value is sent back as the response data. Unregistered
+///commands are answered with a ParseError status, like a device that doesnt implement them. Reset
+///into app isnt answered, the device is busy resetting.
+pub(crate) struct DeviceSimulator {
+    handlers: HashMap<u32, Handler>,
+    incoming: RefCell<Defrag<Vec<u8>>>,
+    outgoing: RefCell<VecDeque<Vec<u8>>>,
+}
+
+impl Default for DeviceSimulator {
+    fn default() -> Self {
+        Self {
+            handlers: HashMap::new(),
+            incoming: RefCell::new(Defrag::new(vec![0; 64 * 1024])),
+            outgoing: RefCell::new(VecDeque::new()),
+        }
+    }
+}
+
+impl DeviceSimulator {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    ///register a handler receiving the command data, LE bytes, and returning the response data.
+    ///Replaces any handler already registered for id.
+    pub(crate) fn handle(mut self, id: u32, handler: impl Fn(&[u8]) -> Vec<u8> + 'static) -> Self {
+        self.handlers.insert(id, Box::new(handler));
+        self
+    }
+
+    fn respond(&self, message: &[u8]) {
+        let id = u32::from_le_bytes([message[0], message[1], message[2], message[3]]);
+        if id == 0x0003 {
+            return;
+        }
+
+        let (status, data) = match self.handlers.get(&id) {
+            Some(handler) => (CommandResponseStatus::Success, handler(&message[8..])),
+            None => (CommandResponseStatus::ParseError, vec![]),
+        };
+
+        //the tag
+        let mut response = message[4..6].to_vec();
+        response.extend_from_slice(&[status as u8, 0x00]);
+        response.extend_from_slice(&data);
+
+        //responses are read without a report ID
+        frame_message(&response, MAX_PACKET_PAYLOAD, |packet| {
+            self.outgoing.borrow_mut().push_back(packet.to_vec());
+            Ok(())
+        })
+        .unwrap();
+    }
+}
+
+impl ReadWrite for DeviceSimulator {
+    fn hf2_write(&self, data: &[u8]) -> Result<usize, Error> {
+        //skip the report ID
+        let message = match defragment(&data[1..], &mut self.incoming.borrow_mut())? {
+            Some(Complete::Message(message)) => Some(message.to_vec()),
+            _ => None,
+        };
+        if let Some(message) = message {
+            self.respond(&message);
+        }
+        Ok(data.len())
+    }
+    fn hf2_read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        match self.outgoing.borrow_mut().pop_front() {
+            Some(report) => {
+                buf[..report.len()].copy_from_slice(&report);
+                Ok(report.len())
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+///A bootloader without BININFO whose pages of flash from 0, erased to 0xFF, are written by
+///WRITE_FLASH_PAGE and read back by CHKSUM_PAGES and READ_WORDS. Returns the flash too, to look at
+///or change behind its back.
+#[cfg(feature = "utils")]
+pub(crate) fn simulated_flash(
+    page_size: usize,
+    pages: usize,
+) -> (DeviceSimulator, Rc<RefCell<Vec<u8>>>) {
+    let u32_at = |data: &[u8], at: usize| {
+        u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]) as usize
+    };
+    let flash = Rc::new(RefCell::new(vec![0xFF_u8; page_size * pages]));
+    let (write, checksum, read) = (flash.clone(), flash.clone(), flash.clone());
+
+    let device = DeviceSimulator::new()
+        .handle(0x0006, move |data| {
+            let page = &data[4..];
+            write.borrow_mut()[u32_at(data, 0)..][..page.len()].copy_from_slice(page);
+            vec![]
+        })
+        .handle(0x0007, move |data| {
+            checksum.borrow()[u32_at(data, 0)..][..u32_at(data, 4) * page_size]
+                .chunks(page_size)
+                .flat_map(|page| {
+                    let mut xmodem = crc_any::CRCu16::crc16xmodem();
+                    xmodem.digest(page);
+                    xmodem.get_crc().to_le_bytes().to_vec()
+                })
+                .collect()
+        })
+        .handle(0x0008, move |data| {
+            read.borrow()[u32_at(data, 0)..][..u32_at(data, 4) * 4].to_vec()
+        });
+    (device, flash)
+}
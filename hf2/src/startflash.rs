@@ -1,9 +1,54 @@
-use crate::command::{rx, xmit, Command};
-use crate::Error;
+use crate::command::{rx_timeout, xmit, Command};
+use crate::{Error, ReadWrite, ERASE_TIMEOUT};
+use std::time::Duration;
 
 /// When issued in bootloader mode, it has no effect. In user-space mode it causes handover to bootloader. A BININFO command can be issued to verify that. Empty tuple response.
-pub fn start_flash(d: &hidapi::HidDevice) -> Result<(), Error> {
+/// Waits up to ERASE_TIMEOUT for the response.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// hf2::start_flash(&d).unwrap();
+/// # }
+/// ```
+pub fn start_flash(d: &impl ReadWrite) -> Result<(), Error> {
+    start_flash_with_timeout(d, ERASE_TIMEOUT)
+}
+
+///start_flash waiting up to timeout for the whole response.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// use std::time::Duration;
+///
+/// // a large flash can take longer to erase than ERASE_TIMEOUT allows
+/// hf2::start_flash_with_timeout(&d, Duration::from_secs(30)).unwrap();
+/// # }
+/// ```
+pub fn start_flash_with_timeout(d: &impl ReadWrite, timeout: Duration) -> Result<(), Error> {
     xmit(Command::new(0x0005, 0, vec![]), d)?;
 
-    rx(d).map(|_| ())
+    rx_timeout(d, timeout).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::tests::CapturingMock;
+
+    #[test]
+    fn start_flash_uses_erase_timeout() {
+        let mock = CapturingMock::with_responses(vec![vec![0x44, 0x00, 0x00, 0x00, 0x00]]);
+
+        start_flash(&mock).unwrap();
+
+        assert_eq!(mock.timeouts(), vec![ERASE_TIMEOUT]);
+    }
 }
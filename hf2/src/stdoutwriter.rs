@@ -0,0 +1,52 @@
+use crate::command::xmit_stdout;
+use crate::ReadWrite;
+use std::io;
+
+///Forwards everything written to it to the devices console as StdOut packets, for devices that
+///accept serial data from the host.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// use std::io::Write;
+///
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// let mut console = hf2::StdoutWriter(d);
+/// writeln!(console, "hello from the host").unwrap();
+/// # }
+/// ```
+pub struct StdoutWriter<T: ReadWrite>(pub T);
+
+impl<T: ReadWrite> io::Write for StdoutWriter<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        xmit_stdout(buf, &self.0).map_err(|e| io::Error::other(format!("{:?}", e)))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::tests::CapturingMock;
+    use std::io::Write;
+
+    #[test]
+    fn writes_stdout_packets() {
+        let data: Vec<u8> = (0..70).collect();
+        let mut writer = StdoutWriter(CapturingMock::new());
+
+        writer.write_all(&data).unwrap();
+
+        let mut first = vec![0x00, 0xBF];
+        first.extend_from_slice(&data[..63]);
+        let mut second = vec![0x00, 0x87];
+        second.extend_from_slice(&data[63..]);
+        assert_eq!(writer.0.captured(), vec![first, second]);
+    }
+}
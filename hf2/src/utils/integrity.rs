@@ -0,0 +1,234 @@
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::Path;
+
+/// What an image has to match before it is flashed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntegrityCheck {
+    /// SHA-256 of the image, ex from a `.sha256` file.
+    Sha256([u8; 32]),
+    /// A minisign public key and a prehashed signature of the image under it, as minisign writes
+    /// them.
+    #[cfg(feature = "minisign")]
+    Minisign {
+        public_key: String,
+        signature: String,
+    },
+}
+
+/// Why an image failed or couldnt be put through an IntegrityCheck.
+#[derive(Debug, PartialEq)]
+pub enum IntegrityError {
+    /// A hash, key, signature or the image itself couldnt be read.
+    File,
+    /// A hash, key or signature that doesnt parse.
+    Malformed,
+    /// The image hashes to actual rather than expected.
+    Sha256Mismatch {
+        expected: [u8; 32],
+        actual: [u8; 32],
+    },
+    /// The signature isnt one of the image under the key.
+    BadSignature,
+}
+
+impl IntegrityCheck {
+    /// The digest in a file as sha256sum writes them, 64 hex digits optionally followed by the file
+    /// name.
+    pub fn sha256_file(path: &Path) -> Result<Self, IntegrityError> {
+        let text = std::fs::read_to_string(path).map_err(|_| IntegrityError::File)?;
+        let digest = text
+            .split_whitespace()
+            .next()
+            .ok_or(IntegrityError::Malformed)?;
+        Self::sha256_hex(digest)
+    }
+
+    pub fn sha256_hex(hex: &str) -> Result<Self, IntegrityError> {
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(IntegrityError::Malformed);
+        }
+        let mut digest = [0_u8; 32];
+        for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| IntegrityError::Malformed)?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| IntegrityError::Malformed)?;
+        }
+        Ok(IntegrityCheck::Sha256(digest))
+    }
+
+    /// The key and signature files minisign writes, checked to parse.
+    #[cfg(feature = "minisign")]
+    pub fn minisign_files(public_key: &Path, signature: &Path) -> Result<Self, IntegrityError> {
+        let public_key = std::fs::read_to_string(public_key).map_err(|_| IntegrityError::File)?;
+        let signature = std::fs::read_to_string(signature).map_err(|_| IntegrityError::File)?;
+        decode_minisign(&public_key, &signature)?;
+        Ok(IntegrityCheck::Minisign {
+            public_key,
+            signature,
+        })
+    }
+
+    /// Checks an image already in memory.
+    pub fn verify(&self, image: &[u8]) -> Result<(), IntegrityError> {
+        read_checked(image, self).map(|_| ())
+    }
+}
+
+/// Reads an image to its end through check, hashing it as it is buffered so a file or stdin is
+/// only read once. The image is only returned if it passes.
+pub fn read_checked(reader: impl Read, check: &IntegrityCheck) -> Result<Vec<u8>, IntegrityError> {
+    match check {
+        IntegrityCheck::Sha256(expected) => {
+            let mut hasher = Sha256::new();
+            let image = read_through(reader, |chunk| hasher.update(chunk))?;
+            let actual: [u8; 32] = hasher.finalize().into();
+            if actual != *expected {
+                return Err(IntegrityError::Sha256Mismatch {
+                    expected: *expected,
+                    actual,
+                });
+            }
+            Ok(image)
+        }
+        #[cfg(feature = "minisign")]
+        IntegrityCheck::Minisign {
+            public_key,
+            signature,
+        } => {
+            let (public_key, signature) = decode_minisign(public_key, signature)?;
+            // only prehashed signatures can be checked a chunk at a time
+            let mut verifier = public_key
+                .verify_stream(&signature)
+                .map_err(|_| IntegrityError::Malformed)?;
+            let image = read_through(reader, |chunk| verifier.update(chunk))?;
+            verifier
+                .finalize()
+                .map_err(|_| IntegrityError::BadSignature)?;
+            Ok(image)
+        }
+    }
+}
+
+fn read_through(
+    mut reader: impl Read,
+    mut hash: impl FnMut(&[u8]),
+) -> Result<Vec<u8>, IntegrityError> {
+    let mut image = Vec::new();
+    let mut buffer = [0_u8; 8192];
+    loop {
+        let count = reader.read(&mut buffer).map_err(|_| IntegrityError::File)?;
+        let chunk = buffer.get(..count).ok_or(IntegrityError::File)?;
+        if chunk.is_empty() {
+            return Ok(image);
+        }
+        hash(chunk);
+        image.extend_from_slice(chunk);
+    }
+}
+
+#[cfg(feature = "minisign")]
+fn decode_minisign(
+    public_key: &str,
+    signature: &str,
+) -> Result<(minisign_verify::PublicKey, minisign_verify::Signature), IntegrityError> {
+    Ok((
+        minisign_verify::PublicKey::decode(public_key.trim())
+            .map_err(|_| IntegrityError::Malformed)?,
+        minisign_verify::Signature::decode(signature.trim())
+            .map_err(|_| IntegrityError::Malformed)?,
+    ))
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    /// SHA-256 of `test`.
+    const TEST_SHA256: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    fn temp_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("hf2-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn sha256_files() {
+        let good = temp_file("good.sha256", &format!("{}  blinky.bin\n", TEST_SHA256));
+        let bare = temp_file("bare.sha256", TEST_SHA256);
+        let empty = temp_file("empty.sha256", "\n");
+        let short = temp_file("short.sha256", &TEST_SHA256[..62]);
+        let not_hex = temp_file("not-hex.sha256", &TEST_SHA256.replace('9', "g"));
+
+        let check = IntegrityCheck::sha256_file(&good);
+        let bare_check = IntegrityCheck::sha256_file(&bare);
+        let malformed: Vec<_> = [&empty, &short, &not_hex]
+            .iter()
+            .map(|path| IntegrityCheck::sha256_file(path))
+            .collect();
+        for path in &[&good, &bare, &empty, &short, &not_hex] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let check = check.unwrap();
+        assert_eq!(bare_check, Ok(check.clone()));
+        for result in malformed {
+            assert_eq!(result, Err(IntegrityError::Malformed));
+        }
+        assert_eq!(
+            IntegrityCheck::sha256_file(&good),
+            Err(IntegrityError::File)
+        );
+        assert_eq!(
+            IntegrityCheck::sha256_hex("é"),
+            Err(IntegrityError::Malformed)
+        );
+
+        assert_eq!(check.verify(b"test"), Ok(()));
+        assert!(matches!(
+            check.verify(b"Test"),
+            Err(IntegrityError::Sha256Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn hashed_as_read() {
+        let check = IntegrityCheck::sha256_hex(TEST_SHA256).unwrap();
+        // handed out in two reads, as a pipe might
+        let reader = Read::chain(&b"te"[..], &b"st"[..]);
+        assert_eq!(read_checked(reader, &check), Ok(b"test".to_vec()));
+        assert!(read_checked(&b"tests"[..], &check).is_err());
+    }
+
+    #[cfg(feature = "minisign")]
+    #[test]
+    fn minisign_signatures() {
+        // minisigns own test vector, a prehashed signature of `test`
+        let public_key = temp_file(
+            "minisign.pub",
+            "untrusted comment: minisign public key E7620F1842B4E81F\n\
+             RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3\n",
+        );
+        let signature = temp_file(
+            "test.minisig",
+            "untrusted comment: signature from minisign secret key\n\
+             RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/\
+             z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=\n\
+             trusted comment: timestamp:1556193335\tfile:test\n\
+             y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==\n",
+        );
+        let garbage = temp_file("garbage.minisig", "untrusted comment: nope\nAAAA\n");
+
+        let check = IntegrityCheck::minisign_files(&public_key, &signature);
+        let malformed = IntegrityCheck::minisign_files(&public_key, &garbage);
+        for path in &[&public_key, &signature, &garbage] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let check = check.unwrap();
+        assert_eq!(malformed, Err(IntegrityError::Malformed));
+        assert_eq!(check.verify(b"test"), Ok(()));
+        assert_eq!(check.verify(b"Test"), Err(IntegrityError::BadSignature));
+    }
+}
@@ -1,13 +1,25 @@
+// elf files and bininfo responses are untrusted input, malformed ones have to be an Err rather
+// than a panic
+#![deny(clippy::indexing_slicing)]
+
 use super::{
-    checksum_pages, reset_into_app, start_flash, write_flash_page, BinInfoMode, BinInfoResponse,
-    Error,
+    bin_info, checksum_pages_batched, read_words, reset_into_app, start_flash, write_flash_page,
+    write_flash_page_with_timeout, BinInfoResponse, ByteLen, CpuArch, Error, FamilyId,
+    ProtocolError, ReadWrite, WordCount, ERASE_TIMEOUT,
 };
+use core::convert::TryFrom;
 use crc_any::CRCu16;
 use goblin::elf::program_header::*;
-use hidapi::HidDevice;
 use std::path::PathBuf;
 use std::{fs::File, io::Read};
 
+/// Checking images against a hash or signature before flashing
+mod integrity;
+pub use integrity::*;
+/// Images from somewhere other than a file, ex an artifact store
+mod provider;
+pub use provider::*;
+
 #[derive(Debug)]
 pub enum UtilError {
     File,
@@ -16,24 +28,86 @@ pub enum UtilError {
     Internal,
     Communication,
     ContentsDifferent,
+    /// The device doesnt implement CHKSUM_PAGES.
+    ChecksumUnsupported,
+    /// The device implements neither CHKSUM_PAGES nor READ_WORDS so nothing could be verified.
+    VerifyUnsupported,
+    /// A page index at or past the devices flash_num_pages.
+    PageOutOfRange,
+    /// The image is built for a different architecture than the device runs.
+    ArchMismatch {
+        image: CpuArch,
+        device: CpuArch,
+    },
+    /// BININFO reported a different page count after flashing, or fewer pages than were written.
+    PageCountChanged {
+        before: u32,
+        after: u32,
+    },
+    /// The page at address read back different from what was just written to it, the device
+    /// acknowledged the write without doing it.
+    WriteIgnored {
+        address: u32,
+    },
+    /// A FirmwareProvider couldnt supply the image, or what it supplied isnt what it declared.
+    /// Nothing was written to the device.
+    Provider(String),
+    /// The image failed the check it came with, ex a FirmwareProvider's declared SHA-256. Nothing
+    /// was written to the device.
+    Integrity(IntegrityError),
+    /// A command sent while flashing or verifying failed, with which one.
+    Protocol(ProtocolError),
+}
+
+/// Maps a failure of command_id to UtilError::Protocol.
+fn protocol(command_id: u32) -> impl Fn(Error) -> UtilError {
+    move |e| UtilError::Protocol(ProtocolError::new(command_id, e))
 }
 
 impl From<Error> for UtilError {
     fn from(err: Error) -> UtilError {
         match err {
-            Error::Parse | Error::Transmission => UtilError::Communication,
+            Error::Parse
+            | Error::Transmission
+            | Error::ResponseTooShort { .. }
+            | Error::Timeout
+            | Error::ShortWrite { .. } => UtilError::Communication,
             _ => UtilError::Internal,
         }
     }
 }
 
+/// Larger than any flash behind an HF2 bootloader, stops a corrupt segment address from zero
+/// filling gigabytes.
+const MAX_IMAGE_SIZE: usize = 64 * 1024 * 1024;
+
 /// Returns a contiguous bin with 0s between non-contiguous sections and starting address from an elf.
+///
+/// # Examples
+///
+/// ```no_run
+/// use hf2::utils::elf_to_bin;
+///
+/// let (binary, address) = elf_to_bin("target/thumbv7em-none-eabihf/release/blinky".into()).unwrap();
+/// ```
 pub fn elf_to_bin(path: PathBuf) -> Result<(Vec<u8>, u32), UtilError> {
     let mut file = File::open(path).map_err(|_| UtilError::File)?;
     let mut buffer = vec![];
     file.read_to_end(&mut buffer).map_err(|_| UtilError::File)?;
 
-    let binary = goblin::elf::Elf::parse(&buffer.as_slice()).map_err(|_| UtilError::Elf)?;
+    elf_bytes_to_bin(&buffer)
+}
+
+/// elf_to_bin and elf_cpu_arch together, reading the elf once.
+pub fn elf_to_bin_and_arch(path: PathBuf) -> Result<(Vec<u8>, u32, Option<CpuArch>), UtilError> {
+    let buffer = std::fs::read(path).map_err(|_| UtilError::File)?;
+
+    let (binary, address) = elf_bytes_to_bin(&buffer)?;
+    Ok((binary, address, elf_bytes_cpu_arch(&buffer)?))
+}
+
+fn elf_bytes_to_bin(buffer: &[u8]) -> Result<(Vec<u8>, u32), UtilError> {
+    let binary = goblin::elf::Elf::parse(buffer).map_err(|_| UtilError::Elf)?;
 
     let mut start_address: u64 = 0;
     let mut last_address: u64 = 0;
@@ -50,149 +124,910 @@ pub fn elf_to_bin(path: PathBuf) -> Result<(Vec<u8>, u32), UtilError> {
         })
         .enumerate()
     {
-        data.extend_from_slice(&buffer[ph.p_offset as usize..][..ph.p_filesz as usize]);
+        // headers can point anywhere, including past the end of the file
+        let segment = usize::try_from(ph.p_offset)
+            .ok()
+            .zip(usize::try_from(ph.p_filesz).ok())
+            .and_then(|(offset, size)| buffer.get(offset..)?.get(..size))
+            .ok_or(UtilError::Elf)?;
+        data.extend_from_slice(segment);
 
         if i == 0 {
             start_address = ph.p_paddr;
         }
         //if any of the rest of the sections are non contiguous, fill zeros
         else {
-            let difference = (ph.p_paddr - last_address) as usize;
+            // overlapping or out of order segments would underflow
+            let difference = ph
+                .p_paddr
+                .checked_sub(last_address)
+                .and_then(|difference| usize::try_from(difference).ok())
+                .ok_or(UtilError::Elf)?;
+            if data.len() + difference > MAX_IMAGE_SIZE {
+                return Err(UtilError::Elf);
+            }
             data.resize(data.len() + difference, 0x0);
         }
 
-        last_address = start_address + ph.p_filesz;
+        last_address = start_address
+            .checked_add(ph.p_filesz)
+            .ok_or(UtilError::Elf)?;
+    }
+
+    let start_address = u32::try_from(start_address).map_err(|_| UtilError::Elf)?;
+
+    Ok((data, start_address))
+}
+
+/// The architecture an ARM elf is built for, from Tag_CPU_arch in its build attributes. None for
+/// elfs that arent ARM or dont say.
+pub fn elf_cpu_arch(path: PathBuf) -> Result<Option<CpuArch>, UtilError> {
+    let buffer = std::fs::read(path).map_err(|_| UtilError::File)?;
+
+    elf_bytes_cpu_arch(&buffer)
+}
+
+fn elf_bytes_cpu_arch(buffer: &[u8]) -> Result<Option<CpuArch>, UtilError> {
+    let elf = goblin::elf::Elf::parse(buffer).map_err(|_| UtilError::Elf)?;
+    if elf.header.e_machine != goblin::elf::header::EM_ARM {
+        return Ok(None);
     }
 
-    Ok((data, start_address as u32))
+    let attributes = elf
+        .section_headers
+        .iter()
+        .find(|sh| matches!(elf.shdr_strtab.get(sh.sh_name), Some(Ok(".ARM.attributes"))))
+        .and_then(|sh| {
+            let offset = usize::try_from(sh.sh_offset).ok()?;
+            let size = usize::try_from(sh.sh_size).ok()?;
+            buffer.get(offset..)?.get(..size)
+        });
+
+    Ok(attributes
+        .and_then(aeabi_cpu_arch)
+        .and_then(|arch| match arch {
+            10 => Some(CpuArch::V7M),
+            11 | 12 => Some(CpuArch::V6M),
+            13 => Some(CpuArch::V7EM),
+            16 => Some(CpuArch::V8MBase),
+            17 => Some(CpuArch::V8MMain),
+            _ => None,
+        }))
+}
+
+/// Tag_CPU_arch from the file attributes of the aeabi subsection of a .ARM.attributes section.
+fn aeabi_cpu_arch(section: &[u8]) -> Option<u64> {
+    fn uleb(data: &[u8], offset: &mut usize) -> Option<u64> {
+        let mut value = 0_u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *data.get(*offset)?;
+            *offset += 1;
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+    fn ntbs(data: &[u8], offset: &mut usize) -> Option<()> {
+        let len = data.get(*offset..)?.iter().position(|byte| *byte == 0)?;
+        *offset += len + 1;
+        Some(())
+    }
+    fn u32_at(data: &[u8], offset: usize) -> Option<usize> {
+        let bytes = <[u8; 4]>::try_from(data.get(offset..)?.get(..4)?).ok()?;
+        Some(u32::from_le_bytes(bytes) as usize)
+    }
+
+    let (version, mut subsections) = section.split_first()?;
+    if *version != b'A' {
+        return None;
+    }
+
+    while !subsections.is_empty() {
+        let len = u32_at(subsections, 0)?;
+        let subsection = subsections.get(4..len)?;
+        subsections = subsections.get(len..)?;
+
+        let vendor_len = subsection.iter().position(|byte| *byte == 0)?;
+        if subsection.get(..vendor_len)? != b"aeabi" {
+            continue;
+        }
+
+        let mut offset = vendor_len + 1;
+        while offset < subsection.len() {
+            let start = offset;
+            let tag = uleb(subsection, &mut offset)?;
+            let size = u32_at(subsection, offset)?;
+            offset += 4;
+            let end = start.checked_add(size)?;
+            // only the whole file attributes, not per section or symbol ones
+            let attributes = subsection.get(offset..end)?;
+            offset = end;
+            if tag != 1 {
+                continue;
+            }
+
+            let mut at = 0;
+            while at < attributes.len() {
+                match uleb(attributes, &mut at)? {
+                    6 => return uleb(attributes, &mut at),
+                    // Tag_compatibility is a flag and a string
+                    32 => {
+                        uleb(attributes, &mut at)?;
+                        ntbs(attributes, &mut at)?;
+                    }
+                    // the rest are strings for CPU names and odd tags past 32, numbers otherwise
+                    4 | 5 => ntbs(attributes, &mut at)?,
+                    tag if tag > 32 && tag & 1 == 1 => ntbs(attributes, &mut at)?,
+                    _ => {
+                        uleb(attributes, &mut at)?;
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Refuses an image built for an architecture the device family cant run, see CpuArch::runs_on.
+/// Either being unknown is only a warning, not every elf has build attributes and not every
+/// bootloader reports a family.
+pub fn check_arch(image: Option<CpuArch>, family_id: Option<FamilyId>) -> Result<(), UtilError> {
+    let device = family_id.as_ref().and_then(FamilyId::cpu_arch);
+
+    match (image, device) {
+        (Some(image), Some(device)) if !image.runs_on(device) => {
+            Err(UtilError::ArchMismatch { image, device })
+        }
+        (Some(_), Some(_)) => Ok(()),
+        _ => {
+            log::warn!(
+                "couldnt check the image architecture {:?} against the device family {:?}",
+                image,
+                family_id
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Drops the first skip bytes of an image, ex the bootloader part of a combined bootloader and app
+/// image. Returns the offset to add to the images start address along with the rest of the image,
+/// which is empty if skip is past the end. FlashOptions::skip does this for flash_bin_with_options.
+///
+/// # Examples
+///
+/// ```
+/// use hf2::utils::slice_image;
+///
+/// let combined = vec![0xFF; 0x8000];
+/// let (offset, app) = slice_image(&combined, 0x4000);
+/// assert_eq!(offset, 0x4000);
+/// assert_eq!(app.len(), 0x4000);
+/// ```
+pub fn slice_image(image: &[u8], skip: usize) -> (u32, &[u8]) {
+    let skip = skip.min(image.len());
+    (skip as u32, image.get(skip..).unwrap_or_default())
+}
+
+/// Pads binary with zeros up to a whole number of pages.
+fn pad_to_pages(binary: &[u8], bininfo: &BinInfoResponse) -> Result<Vec<u8>, UtilError> {
+    // the page size comes from the device
+    if bininfo.flash_page_size == 0 {
+        return Err(UtilError::Communication);
+    }
+    let page_size = bininfo.flash_page_size as usize;
+
+    let mut binary = binary.to_owned();
+
+    let padded_size = match binary.len() % page_size {
+        0 => binary.len(),
+        remainder => binary.len() + (page_size - remainder),
+    };
+    log::debug!(
+        "binary is {} bytes, padding to {} bytes",
+        binary.len(),
+        padded_size
+    );
+    binary.resize(padded_size, 0x0);
+
+    Ok(binary)
 }
 
 /// Flash, Verify and restart into app.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// use hf2::utils::flash_bin;
+///
+/// let binary = std::fs::read("blinky.bin").unwrap();
+/// let bininfo = hf2::bin_info(&d).unwrap();
+/// flash_bin(&binary, 0x4000, &bininfo, &d).unwrap();
+/// # }
+/// ```
 pub fn flash_bin(
     binary: &[u8],
     address: u32,
     bininfo: &BinInfoResponse,
-    d: &HidDevice,
+    d: &impl ReadWrite,
 ) -> Result<(), UtilError> {
+    flash_bin_with_options(binary, address, bininfo, &FlashOptions::default(), d).map(|_| ())
+}
+
+/// Options for flash_bin_with_options, the default flashes like flash_bin.
+#[derive(Debug, Clone)]
+pub struct FlashOptions {
+    /// Check the image against the device and checksum it without erasing, writing or resetting
+    /// anything.
+    pub dry_run: bool,
+    /// Pad the image with 0xFF to the end of flash so nothing of a previous firmware is left
+    /// behind. BININFO doesnt report where flash starts so it is taken to start at 0, as for
+    /// read_page.
+    pub fill_to_end: bool,
+    /// Write the first page, with the apps vector table, after all the others. Bootloaders that
+    /// judge an app valid by its vector table then wont boot a half written image if flashing is
+    /// cut short. On by default.
+    pub first_page_last: bool,
+    /// Read back the first page written and fail with WriteIgnored if it doesnt hold what was
+    /// sent, catching bootloaders that acknowledge writes without doing them before the whole
+    /// image goes out. Costs one page of READ_WORDS.
+    pub paranoid: bool,
+    /// Bytes at the start of the image that arent flashed, ex the bootloader of a combined image.
+    /// address is still where the whole image starts, the rest goes to address + skip.
+    pub skip: usize,
+}
+
+impl Default for FlashOptions {
+    fn default() -> Self {
+        FlashOptions {
+            dry_run: false,
+            fill_to_end: false,
+            first_page_last: true,
+            paranoid: false,
+            skip: 0,
+        }
+    }
+}
+
+/// What flash_bin_with_options did.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlashResult {
+    pub pages_written: u32,
+    /// Pages a dry run would have written, 0 when it wasnt one. Not the bytes left out by
+    /// FlashOptions::skip.
+    pub pages_planned: u32,
+    /// Page data sent to the device, padding included.
+    pub bytes_transferred: u64,
+    /// CRC-16-CCITT of each page of the padded image, what CHKSUM_PAGES reports once it is flashed.
+    pub checksums: Vec<u16>,
+}
+
+/// flash_bin with options.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// use hf2::utils::{flash_bin_with_options, FlashOptions};
+///
+/// let binary = std::fs::read("blinky.bin").unwrap();
+/// let bininfo = hf2::bin_info(&d).unwrap();
+/// let options = FlashOptions {
+///     dry_run: true,
+///     ..Default::default()
+/// };
+/// let result = flash_bin_with_options(&binary, 0x4000, &bininfo, &options, &d).unwrap();
+/// println!("would write {} pages", result.pages_planned);
+/// # }
+/// ```
+pub fn flash_bin_with_options(
+    binary: &[u8],
+    address: u32,
+    bininfo: &BinInfoResponse,
+    options: &FlashOptions,
+    d: &impl ReadWrite,
+) -> Result<FlashResult, UtilError> {
+    let (offset, binary) = slice_image(binary, options.skip);
     if binary.is_empty() {
         return Err(UtilError::InvalidBinary);
     }
+    let address = address
+        .checked_add(offset)
+        .ok_or(UtilError::InvalidBinary)?;
 
-    let mut binary = binary.to_owned();
-
-    // pad zeros to page size
-    // add divisor-1 to dividend to round up
-    let padded_num_pages =
-        (binary.len() as u32 + (bininfo.flash_page_size - 1)) / bininfo.flash_page_size;
+    let mut binary = pad_to_pages(binary, bininfo)?;
+    check_fits(&binary, address, bininfo)?;
+    if options.fill_to_end {
+        fill_to_end(&mut binary, address, bininfo)?;
+    }
+    let num_pages = binary.len() as u32 / bininfo.flash_page_size;
+    let checksums = page_checksums(&binary, bininfo);
 
-    let padded_size = padded_num_pages * bininfo.flash_page_size;
-    log::debug!(
-        "binary is {} bytes, padding to {} bytes",
-        binary.len(),
-        padded_size
-    );
-    for _i in 0..(padded_size as usize - binary.len()) {
-        binary.push(0x0);
+    if options.dry_run {
+        log::info!(
+            "dry run, {} pages at {:#010X} not written",
+            num_pages,
+            address
+        );
+        return Ok(FlashResult {
+            pages_written: 0,
+            pages_planned: num_pages,
+            bytes_transferred: 0,
+            checksums,
+        });
     }
 
-    if bininfo.mode != BinInfoMode::Bootloader {
-        let _ = start_flash(&d).map_err(UtilError::from)?;
+    if !bininfo.is_bootloader() {
+        start_flash(d).map_err(protocol(0x0005))?;
     }
-    flash(&binary, address, &bininfo, &d)?;
+    flash(&binary, address, bininfo, options, d)?;
 
-    match verify(&binary, address, &bininfo, &d) {
+    match verify(&binary, address, bininfo, d) {
         Ok(false) => return Err(UtilError::ContentsDifferent),
         Err(e) => return Err(e),
         Ok(true) => (),
     };
 
-    reset_into_app(&d).map_err(UtilError::from)
+    reset_into_app(d).map_err(protocol(0x0003))?;
+
+    Ok(FlashResult {
+        pages_written: num_pages,
+        pages_planned: 0,
+        bytes_transferred: binary.len() as u64,
+        checksums,
+    })
 }
 
-/// Flashes binary writing a single page at a time.
-fn flash(
-    binary: &[u8],
+/// flash_bin for an image generated on the fly, holding only the page being written rather than
+/// the whole image. The last page is padded with zeros. Pages are written in order so there is no
+/// first_page_last, and the image is verified by checksum once written, which is skipped with a
+/// warning on devices without CHKSUM_PAGES as reading back needs the image.
+///
+/// An iterator whose size_hint already runs past the end of flash is refused before anything is
+/// written. Otherwise the end is only found when it is reached, InvalidBinary is returned with the
+/// pages before it written and the device left in its bootloader. Use flash_bin when that matters.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// use hf2::utils::flash_iter;
+///
+/// let bininfo = hf2::bin_info(&d).unwrap();
+/// let pattern = (0..64 * 1024).map(|i| (i % 251) as u8);
+/// flash_iter(pattern, 0x4000, &bininfo, &d).unwrap();
+/// # }
+/// ```
+pub fn flash_iter(
+    data: impl IntoIterator<Item = u8>,
+    address: u32,
+    bininfo: &BinInfoResponse,
+    d: &impl ReadWrite,
+) -> Result<FlashResult, UtilError> {
+    if bininfo.flash_page_size == 0 {
+        return Err(UtilError::Communication);
+    }
+    if !bininfo.is_aligned(address) {
+        return Err(UtilError::InvalidBinary);
+    }
+    let page_size = bininfo.flash_page_size as usize;
+
+    let mut data = data.into_iter().peekable();
+    if data.peek().is_none() {
+        return Err(UtilError::InvalidBinary);
+    }
+    let (at_least, _) = data.size_hint();
+    if bininfo.flash_num_pages != 0
+        && u64::from(address) + at_least as u64 > bininfo.total_flash_size()
+    {
+        return Err(UtilError::InvalidBinary);
+    }
+    if !bininfo.is_bootloader() {
+        start_flash(d).map_err(protocol(0x0005))?;
+    }
+
+    let mut checksums = vec![];
+    let mut page = Vec::with_capacity(page_size);
+
+    while data.peek().is_some() {
+        page.clear();
+        page.extend(data.by_ref().take(page_size));
+        page.resize(page_size, 0x0);
+
+        let page_index = checksums.len() as u32;
+        if bininfo.flash_num_pages != 0
+            && u64::from(address) + u64::from(page_index + 1) * page_size as u64
+                > bininfo.total_flash_size()
+        {
+            return Err(UtilError::InvalidBinary);
+        }
+        let target_address = bininfo
+            .flash_page_size
+            .checked_mul(page_index)
+            .and_then(|offset| offset.checked_add(address))
+            .ok_or(UtilError::InvalidBinary)?;
+
+        // the first write can wait on an erase
+        if page_index == 0 {
+            write_flash_page_with_timeout(d, target_address, page.clone(), ERASE_TIMEOUT)
+                .map_err(protocol(0x0006))?;
+        } else {
+            write_flash_page(d, target_address, page.clone()).map_err(protocol(0x0006))?;
+        }
+        checksums.extend(page_checksums(&page, bininfo));
+    }
+
+    let num_pages = checksums.len() as u32;
+    match checksum_pages_batched(
+        d,
+        address,
+        num_pages,
+        bininfo.flash_page_size,
+        bininfo.max_message_size,
+    ) {
+        Ok(response) if response.checksums == checksums => (),
+        Ok(_) => return Err(UtilError::ContentsDifferent),
+        Err(Error::CommandNotRecognized) => {
+            log::warn!("no CHKSUM_PAGES, {} pages written unverified", num_pages)
+        }
+        Err(e) => return Err(protocol(0x0007)(e)),
+    }
+
+    reset_into_app(d).map_err(protocol(0x0003))?;
+
+    Ok(FlashResult {
+        pages_written: num_pages,
+        pages_planned: 0,
+        bytes_transferred: u64::from(num_pages) * page_size as u64,
+        checksums,
+    })
+}
+
+/// Checks a padded binary starts on a page and ends before the end of the devices flash. Devices
+/// that dont report their page count skip the size check.
+fn check_fits(binary: &[u8], address: u32, bininfo: &BinInfoResponse) -> Result<(), UtilError> {
+    if !bininfo.is_aligned(address) {
+        return Err(UtilError::InvalidBinary);
+    }
+    if bininfo.flash_num_pages != 0
+        && u64::from(address) + binary.len() as u64 > bininfo.total_flash_size()
+    {
+        return Err(UtilError::InvalidBinary);
+    }
+    Ok(())
+}
+
+/// Pads a page aligned binary with 0xFF up to the end of a flash starting at 0.
+fn fill_to_end(
+    binary: &mut Vec<u8>,
     address: u32,
     bininfo: &BinInfoResponse,
-    d: &HidDevice,
 ) -> Result<(), UtilError> {
-    for (page_index, page) in binary.chunks(bininfo.flash_page_size as usize).enumerate() {
-        let target_address = address + bininfo.flash_page_size * page_index as u32;
+    if bininfo.flash_num_pages == 0 {
+        return Err(UtilError::Communication);
+    }
+    let end = bininfo
+        .total_flash_size()
+        .checked_sub(u64::from(address))
+        .and_then(|len| usize::try_from(len).ok())
+        .ok_or(UtilError::InvalidBinary)?;
 
-        let _ = write_flash_page(&d, target_address, page.to_vec()).map_err(UtilError::from)?;
+    if binary.len() < end {
+        log::debug!("filling {} bytes to the end of flash", end - binary.len());
+        binary.resize(end, 0xFF);
     }
     Ok(())
 }
 
-pub fn verify_bin(
+/// CRC-16-CCITT of each page of binary, matching CHKSUM_PAGES.
+fn page_checksums(binary: &[u8], bininfo: &BinInfoResponse) -> Vec<u16> {
+    binary
+        .chunks(bininfo.flash_page_size as usize)
+        .map(|page| {
+            let mut xmodem = CRCu16::crc16xmodem();
+            xmodem.digest(&page);
+            xmodem.get_crc()
+        })
+        .collect()
+}
+
+/// Page indexes in the order to write them, first_page_last moves page 0 to the end.
+fn write_order(num_pages: usize, first_page_last: bool) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..num_pages).collect();
+    if first_page_last {
+        order.rotate_left(1.min(num_pages));
+    }
+    order
+}
+
+/// Flashes binary writing a single page at a time.
+fn flash(
     binary: &[u8],
     address: u32,
     bininfo: &BinInfoResponse,
-    d: &HidDevice,
+    options: &FlashOptions,
+    d: &impl ReadWrite,
 ) -> Result<(), UtilError> {
-    let mut binary = binary.to_owned();
+    let pages: Vec<&[u8]> = binary.chunks(bininfo.flash_page_size as usize).collect();
 
-    // pad zeros to page size
-    // add divisor-1 to dividend to round up
-    let padded_num_pages =
-        (binary.len() as u32 + (bininfo.flash_page_size - 1)) / bininfo.flash_page_size;
+    for (write_index, page_index) in write_order(pages.len(), options.first_page_last)
+        .into_iter()
+        .enumerate()
+    {
+        let page = pages.get(page_index).ok_or(UtilError::InvalidBinary)?;
+        let target_address = bininfo
+            .flash_page_size
+            .checked_mul(page_index as u32)
+            .and_then(|offset| offset.checked_add(address))
+            .ok_or(UtilError::InvalidBinary)?;
 
-    let padded_size = padded_num_pages * bininfo.flash_page_size;
+        // the first write can wait on an erase
+        if write_index == 0 {
+            write_flash_page_with_timeout(d, target_address, page.to_vec(), ERASE_TIMEOUT)
+                .map_err(protocol(0x0006))?;
 
-    for _i in 0..(padded_size as usize - binary.len()) {
-        binary.push(0x0);
+            if options.paranoid {
+                let readback = read_memory(
+                    target_address,
+                    ByteLen(page.len()),
+                    ReadOrder::Ascending,
+                    bininfo,
+                    d,
+                )?;
+                if readback != *page {
+                    return Err(UtilError::WriteIgnored {
+                        address: target_address,
+                    });
+                }
+            }
+        } else {
+            write_flash_page(d, target_address, page.to_vec()).map_err(protocol(0x0006))?;
+        }
     }
+    Ok(())
+}
+
+/// Verify binary is what is in flash at address, without writing anything.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// use hf2::utils::verify_bin;
+///
+/// let binary = std::fs::read("blinky.bin").unwrap();
+/// let bininfo = hf2::bin_info(&d).unwrap();
+/// verify_bin(&binary, 0x4000, &bininfo, &d).unwrap();
+/// # }
+/// ```
+pub fn verify_bin(
+    binary: &[u8],
+    address: u32,
+    bininfo: &BinInfoResponse,
+    d: &impl ReadWrite,
+) -> Result<(), UtilError> {
+    let binary = pad_to_pages(binary, bininfo)?;
 
-    match verify(&binary, address, &bininfo, &d) {
+    match verify(&binary, address, bininfo, d) {
         Ok(false) => Err(UtilError::ContentsDifferent),
         Err(e) => Err(e),
         Ok(true) => Ok(()),
     }
 }
 
+/// Checks BININFO still reports the page count it did before flashing, bininfo, and that
+/// expected_written pages fit in it. Some bootloaders report fewer pages once they find flash
+/// corrupt, so this is a cheap sanity check short of reading anything back.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// use hf2::utils::{flash_bin_with_options, verify_page_count, FlashOptions};
+///
+/// let binary = std::fs::read("blinky.bin").unwrap();
+/// let bininfo = hf2::bin_info(&d).unwrap();
+/// let options = FlashOptions::default();
+/// let result = flash_bin_with_options(&binary, 0x4000, &bininfo, &options, &d).unwrap();
+/// verify_page_count(&bininfo, result.pages_written, &d).unwrap();
+/// # }
+/// ```
+pub fn verify_page_count(
+    bininfo: &BinInfoResponse,
+    expected_written: u32,
+    d: &impl ReadWrite,
+) -> Result<(), UtilError> {
+    let after = bin_info(d).map_err(UtilError::from)?.flash_num_pages;
+
+    if after != bininfo.flash_num_pages || expected_written > after {
+        return Err(UtilError::PageCountChanged {
+            before: bininfo.flash_num_pages,
+            after,
+        });
+    }
+    Ok(())
+}
+
 /// Verifys checksum of binary.
 fn verify(
     binary: &[u8],
     address: u32,
     bininfo: &BinInfoResponse,
-    d: &HidDevice,
+    d: &impl ReadWrite,
 ) -> Result<bool, UtilError> {
     // get checksums of existing pages
+    let num_pages = binary.len() as u32 / bininfo.flash_page_size;
+    let device_checksums = match checksum_pages_batched(
+        d,
+        address,
+        num_pages,
+        bininfo.flash_page_size,
+        bininfo.max_message_size,
+    ) {
+        Ok(response) => response.checksums,
+        Err(Error::CommandNotRecognized) => {
+            log::debug!("no CHKSUM_PAGES, verifying with READ_WORDS");
+            return verify_by_reading(binary, address, bininfo, d);
+        }
+        Err(e) => return Err(protocol(0x0007)(e)),
+    };
 
-    let top_address = address + binary.len() as u32;
+    Ok(page_checksums(binary, bininfo).eq(&device_checksums))
+}
 
-    let max_pages = bininfo.max_message_size / 2 - 2;
-    let steps = max_pages * bininfo.flash_page_size;
-    let mut device_checksums = vec![];
+/// Verifys by reading the flash back for devices without CHKSUM_PAGES, slow but better than nothing.
+fn verify_by_reading(
+    binary: &[u8],
+    address: u32,
+    bininfo: &BinInfoResponse,
+    d: &impl ReadWrite,
+) -> Result<bool, UtilError> {
+    // response is 4 bytes of header plus 4 bytes per word
+    let max_words = (bininfo.max_message_size.saturating_sub(4) / 4) as usize;
+    if max_words == 0 {
+        return Err(UtilError::Communication);
+    }
 
-    for target_address in (address..top_address).step_by(steps as usize) {
-        // add divisor-1 to dividend to round up
-        let pages_left = (top_address - target_address + (bininfo.flash_page_size - 1))
-            / bininfo.flash_page_size;
+    for (index, chunk) in binary.chunks(max_words * 4).enumerate() {
+        let target_address = ((index * max_words * 4) as u32)
+            .checked_add(address)
+            .ok_or(UtilError::InvalidBinary)?;
 
-        let num_pages = if pages_left < max_pages {
-            pages_left
-        } else {
-            max_pages
+        let device = match read_bytes(d, target_address, ByteLen(chunk.len())) {
+            Ok(device) => device,
+            Err(Error::CommandNotRecognized) => return Err(UtilError::VerifyUnsupported),
+            Err(e) => return Err(protocol(0x0008)(e)),
         };
 
-        let chk = checksum_pages(&d, target_address, num_pages).map_err(UtilError::from)?;
-        device_checksums.extend_from_slice(&chk.checksums);
+        if device != chunk {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Reads len bytes with a single READ_WORDS.
+fn read_bytes(d: &impl ReadWrite, address: u32, len: ByteLen) -> Result<Vec<u8>, Error> {
+    // partial trailing word still has to be read whole
+    let num_words = WordCount::covering(len).ok_or(Error::Arguments)?;
+    let ByteLen(len) = len;
+
+    let words = read_words(d, address, num_words)?.words;
+
+    let mut bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    if bytes.len() < len {
+        return Err(Error::Parse);
+    }
+    bytes.truncate(len);
+    Ok(bytes)
+}
+
+/// Order read_memory reads pages from the device in.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ReadOrder {
+    #[default]
+    Ascending,
+    /// Last page first, for devices that invalidate pages as they are read, ex a filesystem
+    /// growing downward.
+    Descending,
+}
+
+/// Reads len bytes of memory starting at address a page at a time with READ_WORDS. The data is
+/// returned in ascending address order whatever order it was read in.
+pub fn read_memory(
+    address: u32,
+    len: ByteLen,
+    order: ReadOrder,
+    bininfo: &BinInfoResponse,
+    d: &impl ReadWrite,
+) -> Result<Vec<u8>, UtilError> {
+    let ByteLen(len) = len;
+    // a page, or as many words as fit in a response, whichever is smaller
+    let max_bytes = bininfo.max_message_size.saturating_sub(4) as usize & !0x3;
+    let chunk_size = core::cmp::min(max_bytes, bininfo.flash_page_size as usize & !0x3);
+    if chunk_size == 0 {
+        return Err(UtilError::Communication);
+    }
+
+    let mut chunks: Vec<(u32, usize)> = (0..len)
+        .step_by(chunk_size)
+        .map(|offset| {
+            u32::try_from(offset)
+                .ok()
+                .and_then(|offset| offset.checked_add(address))
+                .map(|chunk_address| (chunk_address, core::cmp::min(chunk_size, len - offset)))
+                .ok_or(UtilError::InvalidBinary)
+        })
+        .collect::<Result<_, _>>()?;
+    if order == ReadOrder::Descending {
+        chunks.reverse();
+    }
+
+    let mut data = vec![0_u8; len];
+    for (chunk_address, chunk_len) in chunks {
+        let bytes = read_bytes(d, chunk_address, ByteLen(chunk_len)).map_err(UtilError::from)?;
+        let offset = (chunk_address - address) as usize;
+        data.get_mut(offset..(offset + chunk_len))
+            .ok_or(UtilError::Internal)?
+            .copy_from_slice(&bytes);
     }
 
-    let mut binary_checksums = vec![];
+    Ok(data)
+}
+
+/// Reads flash page page_index with READ_WORDS. BININFO doesnt report where flash starts, so
+/// pages are counted from address 0 as on the SAMD and nRF parts, other parts can use
+/// read_memory with their flash address.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// let bininfo = hf2::bin_info(&d).unwrap();
+/// let page = hf2::utils::read_page(32, &bininfo, &d).unwrap();
+/// # }
+/// ```
+pub fn read_page(
+    page_index: u32,
+    bininfo: &BinInfoResponse,
+    d: &impl ReadWrite,
+) -> Result<Vec<u8>, UtilError> {
+    if page_index >= bininfo.flash_num_pages {
+        return Err(UtilError::PageOutOfRange);
+    }
+    let address = page_index
+        .checked_mul(bininfo.flash_page_size)
+        .ok_or(UtilError::PageOutOfRange)?;
+
+    read_memory(
+        address,
+        ByteLen(bininfo.flash_page_size as usize),
+        ReadOrder::Ascending,
+        bininfo,
+        d,
+    )
+}
+
+/// Short stable identifier of what is in a flash region, from the devices page checksums so the
+/// original binary isnt needed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    pub address: u32,
+    pub page_size: u32,
+    pub checksums: Vec<u16>,
+    /// 64 bit FNV-1a over address, page_size and the number of pages as u32 LE followed by each
+    /// checksum as u16 LE. Devices with the same firmware in the region get the same hash, so this
+    /// must never change between releases.
+    pub hash: u64,
+}
+
+impl Fingerprint {
+    pub fn new(address: u32, page_size: u32, checksums: Vec<u16>) -> Self {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut digest = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= u64::from(*byte);
+                hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        };
 
-    //collect and sums so we can view all mismatches, not just first
-    for page in binary.chunks(bininfo.flash_page_size as usize) {
-        let mut xmodem = CRCu16::crc16xmodem();
-        xmodem.digest(&page);
+        digest(&address.to_le_bytes());
+        digest(&page_size.to_le_bytes());
+        digest(&(checksums.len() as u32).to_le_bytes());
+        for checksum in &checksums {
+            digest(&checksum.to_le_bytes());
+        }
 
-        binary_checksums.push(xmodem.get_crc());
+        Self {
+            address,
+            page_size,
+            checksums,
+            hash,
+        }
     }
+}
 
-    Ok(binary_checksums.eq(&device_checksums))
+impl std::fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:016x}", self.hash)
+    }
 }
 
+/// Fingerprints num_pages of flash starting at address.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// use hf2::utils::flash_fingerprint;
+///
+/// let bininfo = hf2::bin_info(&d).unwrap();
+/// let fingerprint = flash_fingerprint(0x4000, 32, &bininfo, &d).unwrap();
+/// println!("{}", fingerprint);
+/// # }
+/// ```
+pub fn flash_fingerprint(
+    address: u32,
+    num_pages: u32,
+    bininfo: &BinInfoResponse,
+    d: &impl ReadWrite,
+) -> Result<Fingerprint, UtilError> {
+    let checksums = checksum_pages_batched(
+        d,
+        address,
+        num_pages,
+        bininfo.flash_page_size,
+        bininfo.max_message_size,
+    )
+    .map_err(|e| match e {
+        Error::CommandNotRecognized => UtilError::ChecksumUnsupported,
+        e => UtilError::from(e),
+    })?
+    .checksums;
+
+    Ok(Fingerprint::new(
+        address,
+        bininfo.flash_page_size,
+        checksums,
+    ))
+}
+
+/// Known uf2 bootloaders, product ids by vendor id.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// let api = hidapi::HidApi::new().unwrap();
+/// let vendor = hf2::utils::vendor_map();
+/// let known = api.device_list().filter(|device| {
+///     vendor
+///         .get(&device.vendor_id())
+///         .map_or(false, |pids| pids.contains(&device.product_id()))
+/// });
+/// for device in known {
+///     println!("{:04x}:{:04x}", device.vendor_id(), device.product_id());
+/// }
+/// # }
+/// ```
 pub fn vendor_map() -> std::collections::HashMap<u16, Vec<u16>> {
     maplit::hashmap! {
         0x1D50 => vec![0x6110, 0x6112],
@@ -210,7 +1045,13 @@ pub fn vendor_map() -> std::collections::HashMap<u16, Vec<u16>> {
 }
 
 #[cfg(test)]
+#[allow(clippy::indexing_slicing)]
 mod tests {
+    use super::*;
+    use crate::command::tests::{simulated_flash, DeviceSimulator};
+    use crate::BinInfoMode;
+    use std::panic::catch_unwind;
+
     #[test]
     fn elf_rustc_1_44_0() {
         let (_, start_addr) = super::elf_to_bin(
@@ -238,4 +1079,587 @@ mod tests {
         .unwrap();
         assert_eq!(start_addr, 0x4000);
     }
+
+    #[test]
+    fn elf_arch() {
+        let path: std::path::PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src/utils/testdata/blinky_1.44.0",
+        ]
+        .iter()
+        .collect();
+        let mut elf = std::fs::read(&path).unwrap();
+
+        assert_eq!(elf_cpu_arch(path).unwrap(), Some(CpuArch::V7EM));
+
+        // the same image claiming to be built for v6-M, then for x86_64
+        let section = {
+            let parsed = goblin::elf::Elf::parse(&elf).unwrap();
+            let sh = parsed
+                .section_headers
+                .iter()
+                .find(|sh| {
+                    matches!(
+                        parsed.shdr_strtab.get(sh.sh_name),
+                        Some(Ok(".ARM.attributes"))
+                    )
+                })
+                .unwrap();
+            sh.sh_offset as usize..(sh.sh_offset + sh.sh_size) as usize
+        };
+        let tag = section.start
+            + elf[section]
+                .windows(2)
+                .position(|window| window == [0x06, 0x0D])
+                .unwrap();
+        elf[tag + 1] = 0x0C;
+        assert_eq!(elf_bytes_cpu_arch(&elf).unwrap(), Some(CpuArch::V6M));
+
+        elf[18] = 0x3E;
+        assert_eq!(elf_bytes_cpu_arch(&elf).unwrap(), None);
+    }
+
+    #[test]
+    fn arch_check() {
+        assert!(check_arch(Some(CpuArch::V7EM), Some(FamilyId::ATSAMD51)).is_ok());
+        assert!(matches!(
+            check_arch(Some(CpuArch::V6M), Some(FamilyId::ATSAMD51)),
+            Err(UtilError::ArchMismatch {
+                image: CpuArch::V6M,
+                device: CpuArch::V7EM
+            })
+        ));
+
+        // armv7e-m only adds to armv7-m, not the other way around
+        assert!(check_arch(Some(CpuArch::V7M), Some(FamilyId::ATSAMD51)).is_ok());
+        assert!(matches!(
+            check_arch(Some(CpuArch::V7EM), Some(FamilyId::STM32F103)),
+            Err(UtilError::ArchMismatch {
+                image: CpuArch::V7EM,
+                device: CpuArch::V7M
+            })
+        ));
+
+        // unknowns only warn
+        assert!(check_arch(None, Some(FamilyId::ATSAMD21)).is_ok());
+        assert!(check_arch(Some(CpuArch::V6M), None).is_ok());
+        assert!(check_arch(Some(CpuArch::V6M), Some(FamilyId::UNKNOWN(0x1234))).is_ok());
+    }
+
+    #[test]
+    fn elf_malformed_does_not_panic() {
+        let path: std::path::PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "src/utils/testdata/blinky_1.47.0",
+        ]
+        .iter()
+        .collect();
+        let elf = std::fs::read(path).unwrap();
+
+        // every truncation inside the headers plus a sparse sweep over the rest
+        let mut corpus: Vec<Vec<u8>> = (0..256.min(elf.len()))
+            .chain((256..elf.len()).step_by(97))
+            .map(|len| elf[..len].to_vec())
+            .collect();
+        // saturate single bytes across the headers to get absurd offsets, sizes and addresses
+        for i in 0..256.min(elf.len()) {
+            let mut mutated = elf.clone();
+            mutated[i] = 0xFF;
+            corpus.push(mutated);
+        }
+
+        for input in corpus {
+            assert!(catch_unwind(|| super::elf_bytes_to_bin(&input)).is_ok());
+            assert!(catch_unwind(|| super::elf_bytes_cpu_arch(&input)).is_ok());
+        }
+    }
+
+    #[test]
+    fn slice_image_offsets() {
+        let image: Vec<u8> = (0..16).collect();
+
+        assert_eq!(super::slice_image(&image, 0), (0, &image[..]));
+        assert_eq!(super::slice_image(&image, 4), (4, &image[4..]));
+        assert_eq!(super::slice_image(&image, 16), (16, &[][..]));
+        assert_eq!(super::slice_image(&image, 100), (16, &[][..]));
+
+        // a combined image loaded at 0x1000 with the app 0x4000 in
+        let combined = vec![0xAA; 0x4100];
+        let (offset, app) = super::slice_image(&combined, 0x4000);
+        assert_eq!(0x1000 + offset, 0x5000);
+        assert_eq!(app.len(), 0x100);
+    }
+
+    #[test]
+    fn fingerprint_test_vector() {
+        let fingerprint = Fingerprint::new(0x4000, 512, vec![0x1234, 0xABCD, 0x0000]);
+
+        assert_eq!(fingerprint.to_string(), "254ce12a1a7f3f86");
+    }
+
+    fn bininfo() -> BinInfoResponse {
+        BinInfoResponse {
+            mode: BinInfoMode::Bootloader,
+            flash_page_size: 512,
+            flash_num_pages: 1024,
+            max_message_size: 64,
+            family_id: None,
+        }
+    }
+
+    #[test]
+    fn fingerprint_from_device() {
+        let device = DeviceSimulator::new().handle(0x0007, |_| {
+            [0x1234_u16, 0xABCD, 0x0000]
+                .iter()
+                .flat_map(|checksum| checksum.to_le_bytes().to_vec())
+                .collect()
+        });
+
+        let fingerprint = flash_fingerprint(0x4000, 3, &bininfo(), &device).unwrap();
+
+        assert_eq!(
+            fingerprint,
+            Fingerprint::new(0x4000, 512, vec![0x1234, 0xABCD, 0x0000])
+        );
+    }
+
+    #[test]
+    fn fingerprint_unsupported() {
+        let device = DeviceSimulator::new();
+
+        assert!(matches!(
+            flash_fingerprint(0x4000, 3, &bininfo(), &device),
+            Err(UtilError::ChecksumUnsupported)
+        ));
+    }
+
+    #[test]
+    fn verify_falls_back_to_read_words() {
+        let binary: Vec<u8> = (0..1024).map(|i| i as u8).collect();
+        let flash = binary.clone();
+        let device = DeviceSimulator::new().handle(0x0008, move |data| {
+            let address = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+            let num_words = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+            flash[(address - 0x4000)..][..num_words * 4].to_vec()
+        });
+
+        assert!(verify_bin(&binary, 0x4000, &bininfo(), &device).is_ok());
+
+        let mut different = binary.clone();
+        different[700] ^= 0xFF;
+        assert!(matches!(
+            verify_bin(&different, 0x4000, &bininfo(), &device),
+            Err(UtilError::ContentsDifferent)
+        ));
+    }
+
+    #[test]
+    fn verify_unsupported() {
+        let device = DeviceSimulator::new();
+
+        assert!(matches!(
+            verify_bin(&[0xAA; 512], 0x4000, &bininfo(), &device),
+            Err(UtilError::VerifyUnsupported)
+        ));
+    }
+
+    ///DeviceSimulator whose nth write comes up a byte short
+    struct ShortWriteAt {
+        device: DeviceSimulator,
+        short_at: usize,
+        writes: std::cell::Cell<usize>,
+    }
+
+    impl ReadWrite for ShortWriteAt {
+        fn hf2_write(&self, data: &[u8]) -> Result<usize, Error> {
+            let n = self.writes.get();
+            self.writes.set(n + 1);
+            if n == self.short_at {
+                return Ok(data.len() - 1);
+            }
+            self.device.hf2_write(data)
+        }
+        fn hf2_read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+            self.device.hf2_read(buf)
+        }
+    }
+
+    #[test]
+    fn short_write_mid_flash() {
+        let d = ShortWriteAt {
+            device: DeviceSimulator::new().handle(0x0006, |_| vec![]),
+            short_at: 20,
+            writes: std::cell::Cell::new(0),
+        };
+
+        // 512 byte pages take 9 reports each, so this is the third page
+        match flash_bin(&[0xAA; 512 * 4], 0x4000, &bininfo(), &d) {
+            Err(UtilError::Protocol(e)) => {
+                assert_eq!(e.command_name, "WRITE FLASH PAGE");
+                assert!(matches!(
+                    e.source,
+                    Error::ShortWrite {
+                        expected: 65,
+                        wrote: 64
+                    }
+                ));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(d.writes.get(), 21);
+    }
+
+    #[test]
+    fn read_memory_order() {
+        let memory: Vec<u8> = (0..2000).map(|i| (i * 7) as u8).collect();
+        let reads = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let device = {
+            let reads = reads.clone();
+            let memory = memory.clone();
+            DeviceSimulator::new().handle(0x0008, move |data| {
+                let address = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+                let num_words = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+                reads.borrow_mut().push(address);
+                memory[(address - 0x4000)..][..num_words * 4].to_vec()
+            })
+        };
+        let bininfo = BinInfoResponse {
+            max_message_size: 1024,
+            ..bininfo()
+        };
+
+        let ascending = read_memory(
+            0x4000,
+            ByteLen(1998),
+            ReadOrder::Ascending,
+            &bininfo,
+            &device,
+        )
+        .unwrap();
+        let ascending_reads = reads.replace(vec![]);
+        let descending = read_memory(
+            0x4000,
+            ByteLen(1998),
+            ReadOrder::Descending,
+            &bininfo,
+            &device,
+        )
+        .unwrap();
+        let descending_reads = reads.replace(vec![]);
+
+        assert_eq!(ascending, &memory[..1998]);
+        assert_eq!(ascending, descending);
+        assert_eq!(ascending_reads, vec![0x4000, 0x4200, 0x4400, 0x4600]);
+        assert_eq!(descending_reads, vec![0x4600, 0x4400, 0x4200, 0x4000]);
+    }
+
+    #[test]
+    fn dry_run_sends_nothing() {
+        use crate::command::tests::CapturingMock;
+
+        let mock = CapturingMock::new();
+        let options = FlashOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let mut binary = vec![0xFF_u8; 1000];
+        binary[0] = 0x00;
+
+        let result = flash_bin_with_options(&binary, 0x4000, &bininfo(), &options, &mock).unwrap();
+
+        assert!(mock.captured().is_empty());
+        assert_eq!(result.pages_written, 0);
+        assert_eq!(result.pages_planned, 2);
+        assert_eq!(result.bytes_transferred, 0);
+        assert_eq!(result.checksums.len(), 2);
+        assert_ne!(result.checksums[0], result.checksums[1]);
+
+        // not on a page boundary, and bigger than the devices flash
+        assert!(matches!(
+            flash_bin_with_options(&binary, 0x4100, &bininfo(), &options, &mock),
+            Err(UtilError::InvalidBinary)
+        ));
+        assert!(matches!(
+            flash_bin_with_options(&vec![0; 512 * 1025], 0, &bininfo(), &options, &mock),
+            Err(UtilError::InvalidBinary)
+        ));
+    }
+
+    #[test]
+    fn dry_run_checks_the_end_address() {
+        use crate::command::tests::CapturingMock;
+
+        let mock = CapturingMock::new();
+        let options = FlashOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let flash_size = bininfo().total_flash_size() as usize;
+
+        // as big as flash fits at 0 but runs past the end anywhere else
+        assert!(
+            flash_bin_with_options(&vec![0; flash_size], 0, &bininfo(), &options, &mock).is_ok()
+        );
+        assert!(matches!(
+            flash_bin_with_options(&vec![0; flash_size], 0x4000, &bininfo(), &options, &mock),
+            Err(UtilError::InvalidBinary)
+        ));
+        assert!(flash_bin_with_options(
+            &vec![0; flash_size - 0x4000],
+            0x4000,
+            &bininfo(),
+            &options,
+            &mock
+        )
+        .is_ok());
+        assert!(mock.captured().is_empty());
+    }
+
+    #[test]
+    fn skip_flashes_the_rest_of_a_combined_image() {
+        let (device, flash) = simulated_flash(256, 16);
+        let bininfo = BinInfoResponse::manual(256, 16);
+        // a bootloader in the first two pages and the app after it, built to load at 0
+        let combined: Vec<u8> = (0..256 * 3).map(|i| (i / 256) as u8).collect();
+        let options = FlashOptions {
+            skip: 256 * 2,
+            ..Default::default()
+        };
+
+        let result = flash_bin_with_options(&combined, 0, &bininfo, &options, &device).unwrap();
+
+        assert_eq!(result.pages_written, 1);
+        assert_eq!(&flash.borrow()[..256 * 2], &[0xFF; 256 * 2][..]);
+        assert_eq!(&flash.borrow()[256 * 2..256 * 3], &[0x02; 256][..]);
+
+        // nothing left after skipping, or an address past 32 bits
+        let past_end = FlashOptions {
+            skip: 256 * 3,
+            ..Default::default()
+        };
+        assert!(matches!(
+            flash_bin_with_options(&combined, 0, &bininfo, &past_end, &device),
+            Err(UtilError::InvalidBinary)
+        ));
+        assert!(matches!(
+            flash_bin_with_options(&combined, u32::MAX - 256, &bininfo, &options, &device),
+            Err(UtilError::InvalidBinary)
+        ));
+    }
+
+    #[test]
+    fn fill_to_end_erases_trailing_pages() {
+        let (device, flash) = simulated_flash(256, 16);
+        flash.replace(vec![0xAA_u8; 256 * 16]);
+        let bininfo = BinInfoResponse::manual(256, 16);
+        let binary = vec![0x11_u8; 300];
+        let options = FlashOptions {
+            fill_to_end: true,
+            ..Default::default()
+        };
+
+        let result = flash_bin_with_options(&binary, 0x200, &bininfo, &options, &device).unwrap();
+
+        let flash = flash.borrow();
+        assert_eq!(result.pages_written, 14);
+        assert_eq!(&flash[..0x200], &[0xAA; 0x200][..]);
+        assert_eq!(&flash[0x200..][..300], &binary[..]);
+        assert_eq!(&flash[0x400..], &[0xFF; 256 * 12][..]);
+    }
+
+    #[test]
+    fn first_page_written_last() {
+        let written_order = |options: &FlashOptions| {
+            // flash already holding the image, writes are only recorded
+            let (device, flash) = simulated_flash(256, 16);
+            flash.replace(vec![0x00_u8; 256 * 16]);
+            let writes = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+            let device = {
+                let writes = writes.clone();
+                device.handle(0x0006, move |data| {
+                    writes
+                        .borrow_mut()
+                        .push(u32::from_le_bytes([data[0], data[1], data[2], data[3]]));
+                    vec![]
+                })
+            };
+            let bininfo = BinInfoResponse::manual(256, 16);
+
+            let result =
+                flash_bin_with_options(&[0x00; 600], 0x400, &bininfo, options, &device).unwrap();
+            assert_eq!(result.pages_written, 3);
+            writes.replace(vec![])
+        };
+
+        assert_eq!(
+            written_order(&FlashOptions::default()),
+            vec![0x500, 0x600, 0x400]
+        );
+        let options = FlashOptions {
+            first_page_last: false,
+            ..Default::default()
+        };
+        assert_eq!(written_order(&options), vec![0x400, 0x500, 0x600]);
+
+        assert_eq!(write_order(1, true), vec![0]);
+        assert!(write_order(0, true).is_empty());
+    }
+
+    #[test]
+    fn flash_from_iterator() {
+        let (device, flash) = simulated_flash(256, 16);
+        let bininfo = BinInfoResponse::manual(256, 16);
+        let generated = || (0..1000_u32).map(|i| (i * 7 + 1) as u8);
+
+        let result = flash_iter(generated(), 0x200, &bininfo, &device).unwrap();
+
+        let expected: Vec<u8> = generated().collect();
+        assert_eq!(result.pages_written, 4);
+        assert_eq!(result.bytes_transferred, 1024);
+        {
+            let flash = flash.borrow();
+            assert_eq!(&flash[0x200..][..1000], &expected[..]);
+            assert_eq!(&flash[0x200 + 1000..0x600], &[0x00; 24][..]);
+            assert_eq!(&flash[0x600..], &[0xFF; 256 * 10][..]);
+        }
+
+        // empty, not on a page, and running past the end of flash
+        for (data, address) in &[(0, 0x200), (10, 0x210), (256 * 15, 0x200)] {
+            assert!(matches!(
+                flash_iter(
+                    std::iter::repeat_n(0xAB, *data),
+                    *address,
+                    &bininfo,
+                    &device
+                ),
+                Err(UtilError::InvalidBinary)
+            ));
+        }
+        // refused by its size_hint before the first write
+        assert_eq!(&flash.borrow()[0x600..], &[0xFF; 256 * 10][..]);
+
+        // an iterator that doesnt know its length is only stopped at the end of flash
+        let unknown = std::iter::repeat_n(0xAB, 256 * 15).filter(|_| true);
+        assert!(matches!(
+            flash_iter(unknown, 0x200, &bininfo, &device),
+            Err(UtilError::InvalidBinary)
+        ));
+        assert_eq!(&flash.borrow()[0x200..], &[0xAB; 256 * 14][..]);
+    }
+
+    #[test]
+    fn paranoid_catches_ignored_writes() {
+        let flash_with = |ignore_writes: bool, paranoid: bool| {
+            let (device, _) = simulated_flash(256, 16);
+            let writes = std::rc::Rc::new(std::cell::Cell::new(0));
+            let device = if ignore_writes {
+                let count = writes.clone();
+                device.handle(0x0006, move |_| {
+                    count.set(count.get() + 1);
+                    vec![]
+                })
+            } else {
+                device
+            };
+            let options = FlashOptions {
+                paranoid,
+                ..Default::default()
+            };
+            let bininfo = BinInfoResponse::manual(256, 16);
+
+            let result = flash_bin_with_options(&[0x5A; 600], 0x400, &bininfo, &options, &device);
+            (result, writes.get())
+        };
+
+        // the first page written is 0x500, the one at 0x400 goes last
+        let (result, writes) = flash_with(true, true);
+        assert!(matches!(
+            result,
+            Err(UtilError::WriteIgnored { address: 0x500 })
+        ));
+        assert_eq!(writes, 1);
+
+        // without it the whole image goes out before the checksums give it away
+        let (result, writes) = flash_with(true, false);
+        assert!(matches!(result, Err(UtilError::ContentsDifferent)));
+        assert_eq!(writes, 3);
+
+        let (result, _) = flash_with(false, true);
+        assert_eq!(result.unwrap().pages_written, 3);
+    }
+
+    #[test]
+    fn page_count_after_flashing() {
+        let reporting = |num_pages: u32| {
+            DeviceSimulator::new().handle(0x0001, move |_| {
+                [1_u32, 256, num_pages, 320]
+                    .iter()
+                    .flat_map(|field| field.to_le_bytes().to_vec())
+                    .collect()
+            })
+        };
+        let bininfo = BinInfoResponse::manual(256, 16);
+
+        assert!(verify_page_count(&bininfo, 16, &reporting(16)).is_ok());
+        assert!(matches!(
+            verify_page_count(&bininfo, 4, &reporting(12)),
+            Err(UtilError::PageCountChanged {
+                before: 16,
+                after: 12
+            })
+        ));
+        assert!(matches!(
+            verify_page_count(&bininfo, 17, &reporting(16)),
+            Err(UtilError::PageCountChanged { .. })
+        ));
+    }
+
+    #[test]
+    fn read_page_by_index() {
+        let memory: Vec<u8> = (0..4096).map(|i| (i * 13) as u8).collect();
+        let device = {
+            let memory = memory.clone();
+            DeviceSimulator::new().handle(0x0008, move |data| {
+                let address = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+                let num_words = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+                memory[address..][..num_words * 4].to_vec()
+            })
+        };
+        let bininfo = BinInfoResponse {
+            flash_num_pages: 8,
+            ..bininfo()
+        };
+
+        assert_eq!(
+            read_page(3, &bininfo, &device).unwrap(),
+            &memory[1536..2048]
+        );
+        assert!(matches!(
+            read_page(8, &bininfo, &device),
+            Err(UtilError::PageOutOfRange)
+        ));
+    }
+
+    #[test]
+    fn flash_with_manual_geometry() {
+        // a bootloader without BININFO that keeps what is written for CHKSUM_PAGES
+        let (device, flash) = simulated_flash(256, 128);
+
+        assert!(matches!(
+            crate::bin_info_or(&device, None),
+            Err(Error::CommandNotRecognized)
+        ));
+
+        let bininfo = crate::bin_info_or(&device, Some(BinInfoResponse::manual(256, 128))).unwrap();
+        let binary: Vec<u8> = (0..600).map(|i| i as u8).collect();
+
+        flash_bin(&binary, 0x4000, &bininfo, &device).unwrap();
+
+        assert_eq!(&flash.borrow()[0x4000..][..600], &binary[..]);
+        assert_eq!(
+            &flash.borrow()[0x4000 + 600..0x4000 + 768],
+            &[0x00; 168][..]
+        );
+    }
 }
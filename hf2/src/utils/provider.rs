@@ -0,0 +1,246 @@
+use super::{
+    flash_bin_with_options, read_checked, FlashOptions, FlashResult, IntegrityCheck,
+    IntegrityError, UtilError,
+};
+use crate::{BinInfoResponse, ReadWrite};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+
+/// Which image a provider is asked for. What location means is up to the provider, a path, a URL,
+/// an artifact name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageSpec {
+    pub location: String,
+}
+
+impl ImageSpec {
+    pub fn new(location: impl Into<String>) -> Self {
+        ImageSpec {
+            location: location.into(),
+        }
+    }
+}
+
+/// An opened image, read once from the start, and what its source says about it.
+pub struct Image {
+    pub reader: Box<dyn Read>,
+    /// Length the source declares, ex a Content-Length, checked against what is read.
+    pub length: Option<u64>,
+    /// SHA-256 of the image the source declares, ex from a registry's metadata, checked as it is
+    /// read.
+    pub sha256: Option<[u8; 32]>,
+}
+
+impl std::fmt::Debug for Image {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Image")
+            .field("length", &self.length)
+            .field("sha256", &self.sha256)
+            .finish()
+    }
+}
+
+/// Somewhere firmware images come from, so they can be flashed without being written to a file
+/// first. Errors are the message UtilError::Provider carries, ex a 404.
+pub trait FirmwareProvider {
+    fn open(&self, spec: &ImageSpec) -> Result<Image, String>;
+}
+
+/// Images from files, location is a path.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileProvider;
+
+impl FirmwareProvider for FileProvider {
+    fn open(&self, spec: &ImageSpec) -> Result<Image, String> {
+        let file = std::fs::File::open(&spec.location)
+            .map_err(|e| format!("couldnt open {}: {}", spec.location, e))?;
+        let length = file.metadata().map(|metadata| metadata.len()).ok();
+        Ok(Image {
+            reader: Box::new(file),
+            length,
+            sha256: None,
+        })
+    }
+}
+
+/// Images held in memory by location, for tests and for callers that already have the bytes.
+#[derive(Debug, Clone, Default)]
+pub struct BytesProvider {
+    images: HashMap<String, Vec<u8>>,
+}
+
+impl BytesProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, location: impl Into<String>, image: Vec<u8>) {
+        self.images.insert(location.into(), image);
+    }
+}
+
+impl FirmwareProvider for BytesProvider {
+    fn open(&self, spec: &ImageSpec) -> Result<Image, String> {
+        let image = self
+            .images
+            .get(&spec.location)
+            .ok_or_else(|| format!("no image at {}", spec.location))?;
+        Ok(Image {
+            reader: Box::new(std::io::Cursor::new(image.clone())),
+            length: Some(image.len() as u64),
+            sha256: Some(Sha256::digest(image).into()),
+        })
+    }
+}
+
+/// Reads the image at spec from provider, failing with UtilError::Provider if it cant be opened or
+/// read or isnt the length the provider declared, and UtilError::Integrity if it isnt the SHA-256
+/// the provider declared.
+pub fn read_image(provider: &dyn FirmwareProvider, spec: &ImageSpec) -> Result<Vec<u8>, UtilError> {
+    let mut image = provider.open(spec).map_err(UtilError::Provider)?;
+
+    let binary = match image.sha256 {
+        Some(digest) => read_checked(image.reader, &IntegrityCheck::Sha256(digest)),
+        None => {
+            let mut binary = Vec::new();
+            image
+                .reader
+                .read_to_end(&mut binary)
+                .map(|_| binary)
+                .map_err(|_| IntegrityError::File)
+        }
+    };
+    let binary = binary.map_err(|e| match e {
+        IntegrityError::Sha256Mismatch { .. } => UtilError::Integrity(e),
+        _ => UtilError::Provider(format!("couldnt read {}", spec.location)),
+    })?;
+
+    if let Some(length) = image.length {
+        if binary.len() as u64 != length {
+            return Err(UtilError::Provider(format!(
+                "{} declared {} bytes but gave {}",
+                spec.location,
+                length,
+                binary.len()
+            )));
+        }
+    }
+    Ok(binary)
+}
+
+/// flash_bin_with_options for the image at spec from provider.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// use hf2::utils::{flash_from_provider, FileProvider, FlashOptions, ImageSpec};
+///
+/// let bininfo = hf2::bin_info(&d).unwrap();
+/// let spec = ImageSpec::new("blinky.bin");
+/// flash_from_provider(&FileProvider, &spec, 0x4000, &bininfo, &FlashOptions::default(), &d)
+///     .unwrap();
+/// # }
+/// ```
+pub fn flash_from_provider(
+    provider: &dyn FirmwareProvider,
+    spec: &ImageSpec,
+    address: u32,
+    bininfo: &BinInfoResponse,
+    options: &FlashOptions,
+    d: &impl ReadWrite,
+) -> Result<FlashResult, UtilError> {
+    let binary = read_image(provider, spec)?;
+    flash_bin_with_options(&binary, address, bininfo, options, d)
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use crate::command::tests::simulated_flash;
+
+    /// A source declaring one thing and serving another.
+    struct Lying(Vec<u8>, Option<u64>, Option<[u8; 32]>);
+
+    impl FirmwareProvider for Lying {
+        fn open(&self, _: &ImageSpec) -> Result<Image, String> {
+            Ok(Image {
+                reader: Box::new(std::io::Cursor::new(self.0.clone())),
+                length: self.1,
+                sha256: self.2,
+            })
+        }
+    }
+
+    #[test]
+    fn declared_metadata_is_checked() {
+        let image = vec![0xAA_u8; 100];
+        let spec = ImageSpec::new("blinky");
+
+        let mut bytes = BytesProvider::new();
+        bytes.insert("blinky", image.clone());
+        assert_eq!(read_image(&bytes, &spec).unwrap(), image);
+        assert!(matches!(
+            read_image(&bytes, &ImageSpec::new("missing")),
+            Err(UtilError::Provider(_))
+        ));
+
+        assert_eq!(
+            read_image(&Lying(image.clone(), None, None), &spec).unwrap(),
+            image
+        );
+        assert!(matches!(
+            read_image(&Lying(image.clone(), Some(200), None), &spec),
+            Err(UtilError::Provider(_))
+        ));
+        assert!(matches!(
+            read_image(&Lying(image.clone(), Some(100), Some([0; 32])), &spec),
+            Err(UtilError::Integrity(IntegrityError::Sha256Mismatch { .. }))
+        ));
+
+        let path = std::env::temp_dir().join(format!("hf2-provider-{}.bin", std::process::id()));
+        std::fs::write(&path, &image).unwrap();
+        let from_file = read_image(&FileProvider, &ImageSpec::new(path.to_string_lossy()));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(from_file.unwrap(), image);
+    }
+
+    #[test]
+    fn flash_from_memory() {
+        let (device, flash) = simulated_flash(256, 16);
+        let bininfo = BinInfoResponse::manual(256, 16);
+        let binary: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let mut provider = BytesProvider::new();
+        provider.insert("registry/blinky/1.2.0", binary.clone());
+
+        // a provider failure is told apart from a flash one, and nothing is written
+        assert!(matches!(
+            flash_from_provider(
+                &provider,
+                &ImageSpec::new("registry/blinky/9.9.9"),
+                0,
+                &bininfo,
+                &FlashOptions::default(),
+                &device
+            ),
+            Err(UtilError::Provider(_))
+        ));
+        assert_eq!(&flash.borrow()[..], &[0xFF; 256 * 16][..]);
+
+        let result = flash_from_provider(
+            &provider,
+            &ImageSpec::new("registry/blinky/1.2.0"),
+            0,
+            &bininfo,
+            &FlashOptions::default(),
+            &device,
+        )
+        .unwrap();
+        assert_eq!(result.pages_written, 3);
+        assert_eq!(&flash.borrow()[..600], &binary[..]);
+    }
+}
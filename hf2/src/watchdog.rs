@@ -0,0 +1,122 @@
+use crate::{bin_info, Error, ReadWrite};
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+///Wraps a device whose bootloader resets itself after a stretch without HID traffic. Before a write
+///that comes more than ping_interval after the last one, a BININFO is sent to keep the device
+///alive. Pings only go out between commands, never while one is partway sent or its response
+///is unread, and a command with no response, ex a reset, stops pings until the next read.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// use std::time::Duration;
+///
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// let d = hf2::WatchdogHid::new(d, Duration::from_secs(1));
+/// let bininfo = hf2::bin_info(&d).unwrap();
+/// # }
+/// ```
+pub struct WatchdogHid<T: ReadWrite> {
+    inner: T,
+    ping_interval: Duration,
+    last_write: Cell<Instant>,
+    //a command has been written and its response not read yet
+    busy: Cell<bool>,
+}
+
+impl<T: ReadWrite> WatchdogHid<T> {
+    pub fn new(inner: T, ping_interval: Duration) -> Self {
+        WatchdogHid {
+            inner,
+            ping_interval,
+            last_write: Cell::new(Instant::now()),
+            busy: Cell::new(false),
+        }
+    }
+
+    ///Pings the device if ping_interval has passed, for callers about to spend a while on the host
+    ///side between commands.
+    pub fn keep_alive(&self) -> Result<(), Error> {
+        if self.busy.get() || self.last_write.get().elapsed() < self.ping_interval {
+            return Ok(());
+        }
+
+        log::debug!("watchdog ping");
+        bin_info(&self.inner)?;
+        self.last_write.set(Instant::now());
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: ReadWrite> ReadWrite for WatchdogHid<T> {
+    fn hf2_write(&self, data: &[u8]) -> Result<usize, Error> {
+        self.keep_alive()?;
+
+        let written = self.inner.hf2_write(data)?;
+        self.last_write.set(Instant::now());
+        self.busy.set(true);
+        Ok(written)
+    }
+
+    fn hf2_read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let read = self.inner.hf2_read(buf)?;
+        self.busy.set(false);
+        Ok(read)
+    }
+
+    fn hf2_read_timeout(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let read = self.inner.hf2_read_timeout(buf, timeout)?;
+        self.busy.set(false);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::tests::DeviceSimulator;
+    use crate::info;
+    use std::rc::Rc;
+
+    fn counting_device(bininfos: Rc<Cell<usize>>) -> DeviceSimulator {
+        DeviceSimulator::new()
+            .handle(0x0001, move |_| {
+                bininfos.set(bininfos.get() + 1);
+                [1_u32, 256, 64, 512]
+                    .iter()
+                    .flat_map(|field| field.to_le_bytes().to_vec())
+                    .collect()
+            })
+            .handle(0x0002, |_| b"UF2 Bootloader v3.15.0\r\n".repeat(5))
+    }
+
+    #[test]
+    fn pings_between_commands() {
+        let bininfos = Rc::new(Cell::new(0));
+        let d = WatchdogHid::new(counting_device(bininfos.clone()), Duration::ZERO);
+
+        // a long info response doesnt get a ping wedged into it
+        info(&d).unwrap();
+        info(&d).unwrap();
+
+        assert_eq!(bininfos.get(), 2);
+    }
+
+    #[test]
+    fn quiet_within_interval() {
+        let bininfos = Rc::new(Cell::new(0));
+        let d = WatchdogHid::new(counting_device(bininfos.clone()), Duration::from_secs(60));
+
+        info(&d).unwrap();
+        d.keep_alive().unwrap();
+
+        assert_eq!(bininfos.get(), 0);
+    }
+}
@@ -0,0 +1,55 @@
+use crate::Error;
+use core::convert::TryFrom;
+
+///words as little endian bytes, the layout READ_WORDS and WRITE_WORDS put them in.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(hf2::words_to_le_bytes(&[0xDEAD_BEEF]), vec![0xEF, 0xBE, 0xAD, 0xDE]);
+/// ```
+pub fn words_to_le_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+///Little endian bytes back into words, Error::Parse unless the length is a multiple of 4.
+pub fn le_bytes_to_words(bytes: &[u8]) -> Result<Vec<u32>, Error> {
+    if bytes.len() & 0x3 != 0 {
+        return Err(Error::Parse);
+    }
+
+    bytes
+        .chunks_exact(4)
+        .map(|word| {
+            <[u8; 4]>::try_from(word)
+                .map(u32::from_le_bytes)
+                .map_err(|_| Error::Parse)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let words = [0x0000_0000, 0xDEAD_BEEF, 0x0102_0304, u32::MAX];
+        let bytes = words_to_le_bytes(&words);
+
+        assert_eq!(
+            &bytes[4..12],
+            &[0xEF, 0xBE, 0xAD, 0xDE, 0x04, 0x03, 0x02, 0x01]
+        );
+        assert_eq!(le_bytes_to_words(&bytes).unwrap(), words);
+        assert!(le_bytes_to_words(&[]).unwrap().is_empty());
+        assert!(words_to_le_bytes(&[]).is_empty());
+
+        for len in 1..4 {
+            assert!(matches!(
+                le_bytes_to_words(&bytes[..bytes.len() - len]),
+                Err(Error::Parse)
+            ));
+        }
+    }
+}
@@ -1,22 +1,140 @@
-use crate::command::{rx, xmit, Command};
-use crate::Error;
+use crate::command::{rx, rx_timeout, xmit, Command};
+use crate::{is_aligned, Error, ReadWrite, ERASE_TIMEOUT};
 use scroll::Pwrite;
+use std::time::Duration;
 
 ///Write a single page of flash memory. Empty tuple response.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// let bininfo = hf2::bin_info(&d).unwrap();
+/// let page = vec![0xFF; bininfo.flash_page_size as usize];
+/// hf2::write_flash_page(&d, 0x4000, page).unwrap();
+/// # }
+/// ```
 pub fn write_flash_page(
-    d: &hidapi::HidDevice,
+    d: &impl ReadWrite,
     target_address: u32,
     data: Vec<u8>,
 ) -> Result<(), Error> {
+    let buffer = write_flash_page_command(target_address, &data)?;
+    xmit(Command::new(0x0006, 0, buffer), d)?;
+
+    rx(d).map(|_| ())
+}
+
+///write_flash_page waiting up to timeout for the whole response, ex ERASE_TIMEOUT for a first page
+///that triggers an erase.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// let bininfo = hf2::bin_info(&d).unwrap();
+/// let page = vec![0xFF; bininfo.flash_page_size as usize];
+/// hf2::write_flash_page_with_timeout(&d, 0x4000, page, hf2::ERASE_TIMEOUT).unwrap();
+/// # }
+/// ```
+pub fn write_flash_page_with_timeout(
+    d: &impl ReadWrite,
+    target_address: u32,
+    data: Vec<u8>,
+    timeout: Duration,
+) -> Result<(), Error> {
+    let buffer = write_flash_page_command(target_address, &data)?;
+    xmit(Command::new(0x0006, 0, buffer), d)?;
+
+    rx_timeout(d, timeout).map(|_| ())
+}
+
+///Writes each page at its address in the order given, for images that arent one contiguous run.
+///Every address has to be aligned to page_size and every page exactly page_size long, checked
+///before anything is written so a bad entry is an Error::Arguments with flash untouched. The first
+///page gets ERASE_TIMEOUT as it can wait on an erase.
+pub fn write_pages(
+    d: &impl ReadWrite,
+    page_size: u32,
+    pages: &[(u32, &[u8])],
+) -> Result<(), Error> {
+    let valid = |(address, data): &(u32, &[u8])| {
+        is_aligned(*address, page_size) && data.len() == page_size as usize
+    };
+    if page_size == 0 || !pages.iter().all(valid) {
+        return Err(Error::Arguments);
+    }
+
+    for (i, (address, data)) in pages.iter().enumerate() {
+        if i == 0 {
+            write_flash_page_with_timeout(d, *address, data.to_vec(), ERASE_TIMEOUT)?;
+        } else {
+            write_flash_page(d, *address, data.to_vec())?;
+        }
+    }
+    Ok(())
+}
+
+fn write_flash_page_command(target_address: u32, data: &[u8]) -> Result<Vec<u8>, Error> {
     let mut buffer = vec![0_u8; data.len() + 4];
     let mut offset = 0;
 
     buffer.gwrite_with(target_address, &mut offset, scroll::LE)?;
-    for i in &data {
+    for i in data {
         buffer.gwrite_with(i, &mut offset, scroll::LE)?;
     }
 
-    xmit(Command::new(0x0006, 0, buffer), d)?;
+    Ok(buffer)
+}
 
-    rx(d).map(|_| ())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::tests::DeviceSimulator;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn pages_written_in_given_order() {
+        let writes = Rc::new(RefCell::new(vec![]));
+        let device = {
+            let writes = writes.clone();
+            DeviceSimulator::new().handle(0x0006, move |data| {
+                let address = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+                writes.borrow_mut().push((address, data[4]));
+                vec![]
+            })
+        };
+
+        let pages: &[(u32, &[u8])] = &[
+            (0x3000, &[3; 256]),
+            (0x0100, &[1; 256]),
+            (0x2000, &[2; 256]),
+        ];
+        write_pages(&device, 256, pages).unwrap();
+        assert_eq!(
+            *writes.borrow(),
+            vec![(0x3000, 3), (0x0100, 1), (0x2000, 2)]
+        );
+
+        let invalid: &[&[(u32, &[u8])]] = &[
+            &[(0x0100, &[0; 256]), (0x0180, &[0; 256])],
+            &[(0x0100, &[0; 256]), (0x0200, &[0; 255])],
+        ];
+        for pages in invalid {
+            assert!(matches!(
+                write_pages(&device, 256, pages),
+                Err(Error::Arguments)
+            ));
+        }
+        assert!(matches!(
+            write_pages(&device, 0, &[]),
+            Err(Error::Arguments)
+        ));
+        assert_eq!(writes.borrow().len(), 3);
+    }
 }
@@ -1,24 +1,94 @@
 use crate::command::{rx, xmit, Command};
-use crate::Error;
+use crate::{words_to_le_bytes, Error, ReadWrite};
 use scroll::Pwrite;
 
 ///Dual of READ WORDS, with the same constraints. Empty tuple response.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// hf2::write_words(&d, 0x2000_0000, 2, vec![0xDEAD_BEEF, 0xCAFE_F00D]).unwrap();
+/// # }
+/// ```
 pub fn write_words(
-    d: &hidapi::HidDevice,
+    d: &impl ReadWrite,
     target_address: u32,
     num_words: u32,
     words: Vec<u32>,
 ) -> Result<(), Error> {
-    let mut buffer = vec![0_u8; words.len() * 4 + 8];
+    let mut buffer = vec![0_u8; 8];
     let mut offset = 0;
 
     buffer.gwrite_with(target_address, &mut offset, scroll::LE)?;
     buffer.gwrite_with(num_words, &mut offset, scroll::LE)?;
-    for i in words {
-        buffer.gwrite_with(i, &mut offset, scroll::LE)?;
-    }
+    buffer.extend_from_slice(&words_to_le_bytes(&words));
 
     xmit(Command::new(0x0009, 0, buffer), d)?;
 
     rx(d).map(|_| ())
 }
+
+///Write a single word, shorthand for a one word WRITE WORDS. target_address must be word aligned.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "hidapi")] {
+/// # let api = hidapi::HidApi::new().unwrap();
+/// # let d = api.open(0x239A, 0x003D).unwrap();
+/// hf2::write_word(&d, 0x2000_0000, 0xDEAD_BEEF).unwrap();
+/// # }
+/// ```
+pub fn write_word(d: &impl ReadWrite, target_address: u32, value: u32) -> Result<(), Error> {
+    if target_address & 0x3 != 0 {
+        return Err(Error::Arguments);
+    }
+
+    write_words(d, target_address, 1, vec![value])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::tests::CapturingMock;
+
+    #[test]
+    fn request_layout() {
+        let mock = CapturingMock::with_responses(vec![vec![0x44, 0x00, 0x00, 0x00, 0x00]]);
+
+        write_words(&mock, 0x2000_0000, 1, vec![0xDEAD_BEEF]).unwrap();
+
+        assert_eq!(
+            mock.captured(),
+            vec![vec![
+                0x00, 0x54, 0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20,
+                0x01, 0x00, 0x00, 0x00, 0xEF, 0xBE, 0xAD, 0xDE,
+            ]]
+        );
+    }
+
+    #[test]
+    fn write_word_matches_write_words() {
+        let single = CapturingMock::with_responses(vec![vec![0x44, 0x00, 0x00, 0x00, 0x00]]);
+        let words = CapturingMock::with_responses(vec![vec![0x44, 0x00, 0x00, 0x00, 0x00]]);
+
+        write_word(&single, 0x2000_0000, 0xDEAD_BEEF).unwrap();
+        write_words(&words, 0x2000_0000, 1, vec![0xDEAD_BEEF]).unwrap();
+
+        assert_eq!(single.captured(), words.captured());
+    }
+
+    #[test]
+    fn write_word_unaligned() {
+        let mock = CapturingMock::new();
+
+        assert!(matches!(
+            write_word(&mock, 0x2000_0002, 0xDEAD_BEEF),
+            Err(Error::Arguments)
+        ));
+        assert!(mock.captured().is_empty());
+    }
+}
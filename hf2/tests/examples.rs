@@ -0,0 +1,91 @@
+//! Runs the examples device code against a simulated bootloader so they keep compiling and working.
+#![cfg(all(feature = "hidapi", feature = "utils"))]
+
+#[allow(dead_code)]
+#[path = "../examples/custom_command.rs"]
+mod custom_command;
+#[allow(dead_code)]
+#[path = "../examples/dump_flash.rs"]
+mod dump_flash;
+#[allow(dead_code)]
+#[path = "../examples/flash_bin.rs"]
+mod flash_bin;
+#[allow(dead_code)]
+#[path = "../examples/tail_dmesg.rs"]
+mod tail_dmesg;
+
+#[allow(dead_code)]
+#[path = "../src/simulator.rs"]
+mod simulator;
+
+// the names simulator.rs uses from the crate root
+use hf2::{
+    defragment, frame_message, CommandResponseStatus, Complete, Defrag, Error, ReadWrite,
+    MAX_PACKET_PAYLOAD,
+};
+use simulator::{simulated_flash, DeviceSimulator};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const PAGE_SIZE: usize = 256;
+
+/// A bootloader with flash from 0 that also answers BININFO and DMESG, with the logs it returns.
+fn bootloader(pages: usize) -> (DeviceSimulator, Rc<RefCell<String>>) {
+    let logs = Rc::new(RefCell::new(String::new()));
+    let (device, _) = simulated_flash(PAGE_SIZE, pages);
+    let device = {
+        let logs = logs.clone();
+        device
+            .handle(0x0001, move |_| {
+                [1, PAGE_SIZE as u32, pages as u32, PAGE_SIZE as u32 + 64]
+                    .iter()
+                    .flat_map(|field: &u32| field.to_le_bytes().to_vec())
+                    .collect()
+            })
+            .handle(0x0010, move |_| logs.borrow().as_bytes().to_vec())
+    };
+    (device, logs)
+}
+
+#[test]
+fn flash_then_dump() {
+    let (d, _) = bootloader(64);
+    let binary: Vec<u8> = (0..1000).map(|i| (i * 3) as u8).collect();
+
+    let result = flash_bin::flash(&d, &binary, 0x1000).unwrap();
+    let dumped = dump_flash::dump(&d, 0x1000, binary.len()).unwrap();
+
+    assert_eq!(result.pages_written, 4);
+    assert_eq!(dumped, binary);
+}
+
+#[test]
+fn custom_command_round_trip() {
+    // a vendor command answering with its data reversed
+    let (d, _) = bootloader(1);
+    let d = d.handle(0x8001, |data| data.iter().rev().copied().collect());
+    // more than one packet each way
+    let data: Vec<u8> = (0..100).collect();
+
+    let response = custom_command::call(&d, 0x8001, &data).unwrap();
+
+    assert_eq!(response, data.iter().rev().copied().collect::<Vec<_>>());
+    assert!(matches!(
+        custom_command::call(&d, 0x8002, &[]),
+        Err(Error::CommandNotRecognized)
+    ));
+}
+
+#[test]
+fn tail_prints_only_new_logs() {
+    let (d, logs) = bootloader(1);
+    let mut seen = String::new();
+    let mut out = vec![];
+
+    logs.replace("booted\n".into());
+    tail_dmesg::tail(&d, &mut seen, &mut out).unwrap();
+    logs.replace("booted\nflashing\n".into());
+    tail_dmesg::tail(&d, &mut seen, &mut out).unwrap();
+
+    assert_eq!(String::from_utf8(out).unwrap(), "booted\nflashing\n");
+}
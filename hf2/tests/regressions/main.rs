@@ -0,0 +1,54 @@
+//! Minimized inputs that once broke a parser, kept so they never do again. Every file in cases is
+//! run through the parser its name starts with, ex `rx-oversized-length.bin` through rx, and has to
+//! come back as Ok or an Err rather than a panic. A `.expected` file next to a case holds the Debug
+//! of the result it has to give. Add cases with `cargo xtask add-regression <target> <file>`.
+#![cfg(feature = "utils")]
+
+mod route;
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+
+#[test]
+fn regressions() {
+    let dir: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "regressions", "cases"]
+        .iter()
+        .collect();
+
+    let mut cases: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .expect("couldnt read the regression cases")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "bin"))
+        .collect();
+    cases.sort();
+    assert!(!cases.is_empty(), "no cases in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for case in &cases {
+        let name = case.file_name().unwrap().to_string_lossy().into_owned();
+        let target = route::target(&name)
+            .unwrap_or_else(|| panic!("{} doesnt start with one of {:?}", name, route::TARGETS));
+        let data = std::fs::read(case).unwrap();
+
+        let result = match catch_unwind(AssertUnwindSafe(|| route::run(target, &data))) {
+            Ok(result) => result,
+            Err(_) => {
+                failures.push(format!("{} panicked", name));
+                continue;
+            }
+        };
+
+        if let Ok(expected) = std::fs::read_to_string(case.with_extension("expected")) {
+            if result.trim() != expected.trim() {
+                failures.push(format!(
+                    "{} gave\n{}\nexpected\n{}",
+                    name,
+                    result.trim(),
+                    expected.trim()
+                ));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
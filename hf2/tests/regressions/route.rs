@@ -0,0 +1,72 @@
+//! Hands a regression input to the parser its file name prefix names. Shared by the regressions
+//! test and `cargo xtask add-regression`, which minimizes against it.
+
+use hf2::{defragment, BinInfoResponse, CommandResponse, Defrag, Error, InfoResponse, ReadWrite};
+use scroll::{Pread, LE};
+use std::cell::Cell;
+
+/// Parsers with regression cases, named by the prefix of a case file.
+pub const TARGETS: &[&str] = &["packet", "rx", "response", "info", "bininfo", "elf"];
+
+/// Size of a report as read from the device, packet and rx inputs are split into reads of this.
+const REPORT: usize = 64;
+
+/// The target a case file is for, `rx-oversized-length.bin` is for rx.
+pub fn target(file_name: &str) -> Option<&'static str> {
+    let prefix = file_name.split('-').next()?;
+    TARGETS.iter().copied().find(|target| *target == prefix)
+}
+
+/// Runs data through target, returning what it made of it as the Debug of its Result. Panics are
+/// the parsers, and what the regressions are there to catch.
+pub fn run(target: &str, data: &[u8]) -> String {
+    match target {
+        "packet" => {
+            let mut state = Defrag::new(vec![0; 64 * 1024]);
+            let results: Vec<String> = data
+                .chunks(REPORT)
+                .map(|packet| format!("{:?}", defragment(packet, &mut state)))
+                .collect();
+            results.join("\n")
+        }
+        "rx" => format!(
+            "{:?}",
+            hf2::rx(&Reads {
+                data,
+                offset: Cell::new(0)
+            })
+        ),
+        "response" => format!("{:?}", data.pread_with::<CommandResponse>(0, LE)),
+        "info" => format!("{:?}", data.pread_with::<InfoResponse>(0, LE)),
+        "bininfo" => format!("{:?}", data.pread_with::<BinInfoResponse>(0, LE)),
+        "elf" => {
+            let path =
+                std::env::temp_dir().join(format!("hf2-regression-{}.elf", std::process::id()));
+            std::fs::write(&path, data).expect("couldnt write the elf to a temp file");
+            let result = hf2::utils::elf_to_bin(path.clone());
+            let _ = std::fs::remove_file(&path);
+            format!("{:?}", result)
+        }
+        _ => panic!("unknown regression target {}", target),
+    }
+}
+
+/// A device whose reads are data a report at a time, then nothing.
+struct Reads<'a> {
+    data: &'a [u8],
+    offset: Cell<usize>,
+}
+
+impl ReadWrite for Reads<'_> {
+    fn hf2_write(&self, data: &[u8]) -> Result<usize, Error> {
+        Ok(data.len())
+    }
+
+    fn hf2_read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let rest = &self.data[self.offset.get()..];
+        let count = rest.len().min(REPORT).min(buf.len());
+        buf[..count].copy_from_slice(&rest[..count]);
+        self.offset.set(self.offset.get() + count);
+        Ok(count)
+    }
+}
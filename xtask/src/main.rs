@@ -0,0 +1,481 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use structopt::StructOpt;
+
+#[allow(dead_code)]
+#[path = "../../hf2/tests/regressions/route.rs"]
+mod route;
+
+fn main() {
+    let opt = Opt::from_args();
+
+    match opt {
+        Opt::AddCommand {
+            id,
+            name,
+            request_fields,
+            response_type,
+        } => {
+            let fields = parse_fields(&request_fields).expect("invalid --request-fields");
+            let module = name.to_lowercase();
+            let source = command_source(id, &name, &fields, response_type);
+
+            let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "..", "hf2", "src"]
+                .iter()
+                .collect::<PathBuf>()
+                .join(format!("{}.rs", module));
+
+            // refuse to clobber an existing command
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .expect("Couldn't create command file, does it already exist?");
+            file.write_all(source.as_bytes())
+                .expect("Couldn't write command file");
+
+            // best effort, the generated source compiles either way
+            let _ = Command::new("rustfmt")
+                .arg("--edition")
+                .arg("2018")
+                .arg(&path)
+                .status();
+
+            println!("wrote {:?}, now add to hf2/src/lib.rs:\n", path);
+            println!("///TODO describe the {} command.", upper_snake(&name));
+            println!("mod {};", module);
+            println!("pub use {}::*;", module);
+        }
+        Opt::AddRegression {
+            target,
+            file,
+            name,
+            expect,
+        } => {
+            if !route::TARGETS.contains(&target.as_str()) {
+                eprintln!("target should be one of {:?}", route::TARGETS);
+                std::process::exit(1);
+            }
+            let data = std::fs::read(&file).expect("Couldn't read the input");
+
+            // the parsers panics are what we are after, not something to print hundreds of times
+            std::panic::set_hook(Box::new(|_| {}));
+            let panicked = panics(&target, &data);
+            let data = if panicked {
+                let minimized = minimize(data.clone(), |data| panics(&target, data));
+                println!(
+                    "panics, minimized {} bytes to {}",
+                    data.len(),
+                    minimized.len()
+                );
+                minimized
+            } else {
+                println!("doesnt panic, installing as is");
+                data
+            };
+            let result = if panicked {
+                None
+            } else {
+                Some(route::run(&target, &data))
+            };
+            let _ = std::panic::take_hook();
+
+            let name = name.unwrap_or_else(|| {
+                file.file_stem()
+                    .expect("input has no file name")
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            let cases: PathBuf = [env!("CARGO_MANIFEST_DIR"), "..", "hf2", "tests"]
+                .iter()
+                .collect::<PathBuf>()
+                .join("regressions")
+                .join("cases");
+            let path = cases.join(format!("{}-{}.bin", target, name));
+            create_new(&path, &data);
+            println!("wrote {:?}", path);
+
+            if expect {
+                match result {
+                    Some(result) => {
+                        let expected = path.with_extension("expected");
+                        create_new(&expected, format!("{}\n", result).as_bytes());
+                        println!("wrote {:?}", expected);
+                    }
+                    None => println!(
+                        "no .expected for a case that panics, fix the parser and write it by hand"
+                    ),
+                }
+            }
+        }
+    }
+}
+
+fn create_new(path: &Path, contents: &[u8]) {
+    // refuse to clobber an existing case
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .expect("Couldn't create case file, does it already exist?");
+    file.write_all(contents).expect("Couldn't write case file");
+}
+
+fn panics(target: &str, data: &[u8]) -> bool {
+    std::panic::catch_unwind(|| route::run(target, data)).is_err()
+}
+
+/// Shrinks data for as long as fails holds, removing chunks of halving size down to single bytes.
+fn minimize(mut data: Vec<u8>, fails: impl Fn(&[u8]) -> bool) -> Vec<u8> {
+    let mut chunk = data.len() / 2;
+    while chunk > 0 {
+        let mut start = 0;
+        while start < data.len() {
+            let end = (start + chunk).min(data.len());
+            let mut candidate = data[..start].to_vec();
+            candidate.extend_from_slice(&data[end..]);
+            if fails(&candidate) {
+                data = candidate;
+            } else {
+                start += chunk;
+            }
+        }
+        chunk /= 2;
+    }
+    data
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FieldType {
+    U8,
+    U16,
+    U32,
+}
+
+impl FieldType {
+    fn size(self) -> usize {
+        match self {
+            FieldType::U8 => 1,
+            FieldType::U16 => 2,
+            FieldType::U32 => 4,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            FieldType::U8 => "u8",
+            FieldType::U16 => "u16",
+            FieldType::U32 => "u32",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ResponseType {
+    /// Empty tuple response
+    None,
+    /// Response data handed back as is
+    RawBytes,
+    /// Response data decoded as utf8, like info and dmesg
+    String,
+}
+
+impl std::str::FromStr for ResponseType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "None" => Ok(ResponseType::None),
+            "RawBytes" => Ok(ResponseType::RawBytes),
+            "String" => Ok(ResponseType::String),
+            _ => Err(format!("unknown response type {}", s)),
+        }
+    }
+}
+
+/// Parses `name:type,name:type` where type is one of u8, u16, u32.
+fn parse_fields(input: &str) -> Result<Vec<(String, FieldType)>, String> {
+    input
+        .split(',')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            let mut parts = field.splitn(2, ':');
+            let name = parts.next().unwrap_or_default().trim();
+            let ty = match parts.next().map(str::trim) {
+                Some("u8") => FieldType::U8,
+                Some("u16") => FieldType::U16,
+                Some("u32") => FieldType::U32,
+                _ => return Err(format!("field {} needs a type of u8, u16 or u32", field)),
+            };
+            if name.is_empty() {
+                return Err(format!("field {} needs a name", field));
+            }
+            Ok((name.to_string(), ty))
+        })
+        .collect()
+}
+
+/// ReadFlashMetadata -> read_flash_metadata
+fn snake(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i != 0 {
+            out.push('_');
+        }
+        out.extend(c.to_lowercase());
+    }
+    out
+}
+
+/// ReadFlashMetadata -> READ_FLASH_METADATA
+fn upper_snake(name: &str) -> String {
+    snake(name).to_uppercase()
+}
+
+/// Emits a command module following the layout of the existing commands, ex readwords.rs.
+fn command_source(
+    id: u32,
+    name: &str,
+    fields: &[(String, FieldType)],
+    response_type: ResponseType,
+) -> String {
+    let function = snake(name);
+    let response = format!("{}Response", name);
+    let mut src = String::new();
+
+    // imports
+    if response_type == ResponseType::None {
+        src.push_str("use crate::command::{rx, xmit, Command};\n");
+    } else {
+        src.push_str(
+            "use crate::command::{rx, xmit, Command, CommandResponse, CommandResponseStatus};\n",
+        );
+    }
+    src.push_str("use crate::{Error, ReadWrite};\n");
+    match (response_type == ResponseType::None, fields.is_empty()) {
+        (true, true) => {}
+        (true, false) => src.push_str("use scroll::Pwrite;\n"),
+        (false, true) => src.push_str("use scroll::{ctx, Pread, LE};\n"),
+        (false, false) => src.push_str("use scroll::{ctx, Pread, Pwrite, LE};\n"),
+    }
+    src.push('\n');
+
+    // command function
+    let returns = if response_type == ResponseType::None {
+        "()".to_string()
+    } else {
+        response.clone()
+    };
+    src.push_str(&format!(
+        "///TODO describe the {} command.\n",
+        upper_snake(name)
+    ));
+    if fields.is_empty() {
+        src.push_str(&format!(
+            "pub fn {}(d: &impl ReadWrite) -> Result<{}, Error> {{\n",
+            function, returns
+        ));
+        src.push_str(&format!(
+            "    xmit(Command::new(0x{:04X}, 0, vec![]), d)?;\n\n",
+            id
+        ));
+    } else {
+        src.push_str(&format!("pub fn {}(\n    d: &impl ReadWrite,\n", function));
+        for (field, ty) in fields {
+            src.push_str(&format!("    {}: {},\n", field, ty.name()));
+        }
+        src.push_str(&format!(") -> Result<{}, Error> {{\n", returns));
+
+        let size: usize = fields.iter().map(|(_, ty)| ty.size()).sum();
+        src.push_str(&format!("    let mut buffer = vec![0_u8; {}];\n", size));
+        src.push_str("    let mut offset = 0;\n\n");
+        for (field, _) in fields {
+            src.push_str(&format!(
+                "    buffer.gwrite_with({}, &mut offset, scroll::LE)?;\n",
+                field
+            ));
+        }
+        src.push_str(&format!(
+            "\n    xmit(Command::new(0x{:04X}, 0, buffer), d)?;\n\n",
+            id
+        ));
+    }
+    if response_type == ResponseType::None {
+        src.push_str("    rx(d).map(|_| ())\n}\n");
+    } else {
+        src.push_str(
+            "    match rx(d) {
+        Ok(CommandResponse {
+            status: CommandResponseStatus::Success,
+            data,
+            ..
+        }) => (data.as_slice()).pread_with(0, LE),
+        Ok(_) => Err(Error::CommandNotRecognized),
+        Err(e) => Err(e),
+    }
+}
+",
+        );
+    }
+
+    // response struct and parsing
+    let (field, field_type, parse) = match response_type {
+        ResponseType::None => ("", "", ""),
+        ResponseType::RawBytes => ("data", "Vec<u8>", "this.to_vec()"),
+        ResponseType::String => ("text", "String", "core::str::from_utf8(this)?.into()"),
+    };
+    if response_type != ResponseType::None {
+        src.push_str(&format!(
+            "
+///Response to the {function} command
+#[derive(Debug, PartialEq)]
+pub struct {response} {{
+    pub {field}: {field_type},
+}}
+
+impl<'a> ctx::TryFromCtx<'a, scroll::Endian> for {response} {{
+    type Error = Error;
+    fn try_from_ctx(this: &'a [u8], _le: scroll::Endian) -> Result<(Self, usize), Self::Error> {{
+        Ok(({response} {{ {field}: {parse} }}, this.len()))
+    }}
+}}
+",
+            function = function,
+            response = response,
+            field = field,
+            field_type = field_type,
+            parse = parse,
+        ));
+    }
+
+    // a round trip test against the simulated device to start from
+    let args: String = fields.iter().map(|_| ", 0").collect();
+    let (reply, expected) = match response_type {
+        ResponseType::None => ("vec![]", "()".to_string()),
+        ResponseType::RawBytes => (
+            "vec![0x01, 0x02]",
+            format!("{} {{ data: vec![0x01, 0x02] }}", response),
+        ),
+        ResponseType::String => (
+            "b\"ok\".to_vec()",
+            format!("{} {{ text: \"ok\".into() }}", response),
+        ),
+    };
+    src.push_str(&format!(
+        "
+#[cfg(test)]
+mod tests {{
+    use super::*;
+    use crate::command::tests::DeviceSimulator;
+
+    #[test]
+    fn round_trip() {{
+        let device = DeviceSimulator::new().handle(0x{id:04X}, |_| {reply});
+
+        assert_eq!({function}(&device{args}).unwrap(), {expected});
+    }}
+}}
+",
+        id = id,
+        reply = reply,
+        function = function,
+        args = args,
+        expected = expected,
+    ));
+
+    src
+}
+
+fn parse_hex_32(input: &str) -> Result<u32, std::num::ParseIntError> {
+    match input.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => input.parse::<u32>(),
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "xtask", about = "hf2-rs development tasks")]
+enum Opt {
+    /// generate a new command module in hf2/src with a round trip test, ex `cargo xtask add-command --id 0x000A --name ReadFlashMetadata --request-fields "addr:u32,len:u32" --response-type RawBytes`
+    AddCommand {
+        #[structopt(long = "id", parse(try_from_str = parse_hex_32))]
+        id: u32,
+        /// command name in CamelCase
+        #[structopt(long = "name")]
+        name: String,
+        /// comma separated name:type pairs, types are u8, u16 or u32
+        #[structopt(long = "request-fields", default_value = "")]
+        request_fields: String,
+        /// None, RawBytes or String
+        #[structopt(long = "response-type", default_value = "None")]
+        response_type: ResponseType,
+    },
+    /// minimize an input that panics a parser and add it to the regression cases in hf2/tests/regressions, ex `cargo xtask add-regression rx crash-5f3a.bin`
+    AddRegression {
+        /// parser the input is for, one of packet, rx, response, info, bininfo or elf
+        target: String,
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// case name after the target prefix, the input file name if not given
+        #[structopt(long = "name")]
+        name: Option<String>,
+        /// also write what the parser returns now as the result the case has to keep giving
+        #[structopt(long = "expect")]
+        expect: bool,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimizes_to_what_fails() {
+        let data = vec![1, 2, 3, 0xAA, 4, 5, 6, 7, 8];
+        assert_eq!(minimize(data, |data| data.contains(&0xAA)), vec![0xAA]);
+
+        let data = (0..40).collect();
+        let minimized = minimize(data, |data| data.len() >= 3 && data[0] == 0);
+        assert_eq!(minimized.len(), 3);
+        assert_eq!(minimized[0], 0);
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(
+            parse_fields("addr:u32, len:u16,flag:u8").unwrap(),
+            vec![
+                ("addr".to_string(), FieldType::U32),
+                ("len".to_string(), FieldType::U16),
+                ("flag".to_string(), FieldType::U8),
+            ]
+        );
+        assert_eq!(parse_fields("").unwrap(), vec![]);
+        assert!(parse_fields("addr").is_err());
+        assert!(parse_fields("addr:u64").is_err());
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(snake("ReadFlashMetadata"), "read_flash_metadata");
+        assert_eq!(upper_snake("BinInfo"), "BIN_INFO");
+    }
+
+    #[test]
+    fn source() {
+        let src = command_source(
+            0x000A,
+            "ReadFlashMetadata",
+            &parse_fields("addr:u32,len:u32").unwrap(),
+            ResponseType::RawBytes,
+        );
+
+        assert!(src.contains("pub fn read_flash_metadata(\n    d: &impl ReadWrite,\n    addr: u32,\n    len: u32,\n) -> Result<ReadFlashMetadataResponse, Error> {"));
+        assert!(src.contains("let mut buffer = vec![0_u8; 8];"));
+        assert!(src.contains("xmit(Command::new(0x000A, 0, buffer), d)?;"));
+        assert!(src.contains("pub struct ReadFlashMetadataResponse {\n    pub data: Vec<u8>,\n}"));
+    }
+}